@@ -2,9 +2,46 @@
 extends EditorPlugin
 
 
+const TEXTFILE_EXTENSIONS_SETTING := "docks/filesystem/textfile_extensions"
+
+
+var import_plugin: EditorImportPlugin
+var export_plugin: EditorExportPlugin
+var syntax_highlighter: EditorSyntaxHighlighter
+# Whether "ce" was added to the text file extensions, and must be removed when the plugin is disabled
+var added_textfile_extension := false
+
+
 func _enter_tree() -> void:
-	pass
+	import_plugin = CerealImportPlugin.new()
+	add_import_plugin(import_plugin)
+	export_plugin = CerealExportPlugin.new()
+	add_export_plugin(export_plugin)
+
+	# .ce files only open in the script editor if they are listed as text files
+	var settings := get_editor_interface().get_editor_settings()
+	var extensions: String = settings.get_setting(TEXTFILE_EXTENSIONS_SETTING)
+	if not "ce" in extensions.split(",", false):
+		settings.set_setting(TEXTFILE_EXTENSIONS_SETTING, extensions + ",ce" if extensions else "ce")
+		added_textfile_extension = true
+
+	syntax_highlighter = CerealSyntaxHighlighter.new()
+	get_editor_interface().get_script_editor().register_syntax_highlighter(syntax_highlighter)
 
 
 func _exit_tree() -> void:
-	pass
+	remove_import_plugin(import_plugin)
+	import_plugin = null
+	remove_export_plugin(export_plugin)
+	export_plugin = null
+
+	get_editor_interface().get_script_editor().unregister_syntax_highlighter(syntax_highlighter)
+	syntax_highlighter = null
+
+	if added_textfile_extension:
+		var settings := get_editor_interface().get_editor_settings()
+		var extensions: PackedStringArray = settings.get_setting(TEXTFILE_EXTENSIONS_SETTING).split(",", false)
+		if "ce" in extensions:
+			extensions.remove_at(extensions.find("ce"))
+			settings.set_setting(TEXTFILE_EXTENSIONS_SETTING, ",".join(extensions))
+		added_textfile_extension = false
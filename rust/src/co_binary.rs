@@ -0,0 +1,915 @@
+use std::collections::HashMap;
+
+use godot::prelude::*;
+use godot::builtin::real;
+
+use crate::co_create::{Mark, References};
+use crate::co_parser::{ParseError, ParseErrorCode, Position};
+
+
+// Binary CE starts with the magic bytes, the version and the flags.
+// If FLAG_STRING_TABLE is set, the string table follows: its length then each string.
+// The value comes last, as a type tag followed by its content:
+// - integers are zigzag LEB128 varints, lengths and string table indices are LEB128 varints
+// - floats, reals and the elements of packed arrays are raw little-endian numbers
+// - strings are either an index in the string table, or their byte length followed by their UTF-8 bytes
+// - shared containers are preceded by an anchor tag and id, and written as a reference tag and id afterwards
+
+const MAGIC: &[u8; 4] = b"CEB\0";
+const VERSION: u8 = 1;
+
+/// Strings are stored once in a table, values only hold their index
+const FLAG_STRING_TABLE: u8 = 1;
+/// Reals (vector components...) are stored as f64 rather than f32
+const FLAG_DOUBLE_REALS: u8 = 2;
+
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_INT: u8 = 3;
+const TAG_FLOAT: u8 = 4;
+const TAG_STRING: u8 = 5;
+const TAG_STRING_NAME: u8 = 6;
+const TAG_NODE_PATH: u8 = 7;
+const TAG_VECTOR2: u8 = 8;
+const TAG_VECTOR2I: u8 = 9;
+const TAG_RECT2: u8 = 10;
+const TAG_RECT2I: u8 = 11;
+const TAG_VECTOR3: u8 = 12;
+const TAG_VECTOR3I: u8 = 13;
+const TAG_TRANSFORM2D: u8 = 14;
+const TAG_VECTOR4: u8 = 15;
+const TAG_VECTOR4I: u8 = 16;
+const TAG_PLANE: u8 = 17;
+const TAG_QUATERNION: u8 = 18;
+const TAG_AABB: u8 = 19;
+const TAG_BASIS: u8 = 20;
+const TAG_TRANSFORM3D: u8 = 21;
+const TAG_PROJECTION: u8 = 22;
+const TAG_COLOR: u8 = 23;
+const TAG_ARRAY: u8 = 24;
+const TAG_DICTIONARY: u8 = 25;
+const TAG_OBJECT: u8 = 26;
+const TAG_PACKED_BYTE_ARRAY: u8 = 27;
+const TAG_PACKED_INT32_ARRAY: u8 = 28;
+const TAG_PACKED_INT64_ARRAY: u8 = 29;
+const TAG_PACKED_FLOAT32_ARRAY: u8 = 30;
+const TAG_PACKED_FLOAT64_ARRAY: u8 = 31;
+const TAG_PACKED_STRING_ARRAY: u8 = 32;
+const TAG_PACKED_VECTOR2_ARRAY: u8 = 33;
+const TAG_PACKED_VECTOR3_ARRAY: u8 = 34;
+const TAG_PACKED_COLOR_ARRAY: u8 = 35;
+const TAG_PACKED_VECTOR4_ARRAY: u8 = 36;
+const TAG_STRING_NAME_ARRAY: u8 = 37;
+const TAG_NODE_PATH_ARRAY: u8 = 38;
+const TAG_ANCHOR: u8 = 64;
+const TAG_REFERENCE: u8 = 65;
+
+
+type StringNameArray = Array<StringName>;
+type NodePathArray = Array<NodePath>;
+
+
+/// Encodes the variant in binary CE. The string table makes repeated strings (such as dictionary keys) cheaper.
+pub fn encode(variant: &Variant, use_string_table: bool) -> PackedByteArray {
+    let mut encoder = Encoder {
+        body: Vec::new(),
+        string_ids: use_string_table.then(HashMap::new),
+        strings: Vec::new(),
+        refs: References::new(variant)
+    };
+    _encode(variant, &mut encoder, 0);
+
+    let mut flags = 0;
+    if use_string_table {
+        flags |= FLAG_STRING_TABLE;
+    }
+    if std::mem::size_of::<real>() == 8 {
+        flags |= FLAG_DOUBLE_REALS;
+    }
+
+    let mut bytes = Vec::with_capacity(encoder.body.len() + 16);
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(VERSION);
+    bytes.push(flags);
+    if use_string_table {
+        _write_varint(&mut bytes, encoder.strings.len() as u64);
+        for string in &encoder.strings {
+            _write_varint(&mut bytes, string.len() as u64);
+            bytes.extend_from_slice(string.as_bytes());
+        }
+    }
+    bytes.extend_from_slice(&encoder.body);
+
+    PackedByteArray::from(bytes.as_slice())
+}
+
+
+/// Decodes binary CE. The errors have no line nor column, only the byte offset.
+/// Objects are only created if allowed, see CerealObject::parse_string.
+pub fn decode(bytes: &[u8], allow_objects: bool) -> Result<Variant, ParseError> {
+    let mut decoder = Decoder {
+        bytes,
+        offset: 0,
+        double_reals: false,
+        strings: None,
+        anchors: HashMap::new(),
+        allow_objects,
+        objects: Vec::new()
+    };
+
+    if decoder.read_bytes(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err(decoder.error(ParseErrorCode::InvalidHeader, "Not binary CE data"));
+    }
+    let version = decoder.read_u8()?;
+    if version != VERSION {
+        return Err(decoder.error(ParseErrorCode::InvalidHeader, &format!("Unsupported binary CE version {}", version)));
+    }
+    let flags = decoder.read_u8()?;
+    decoder.double_reals = flags & FLAG_DOUBLE_REALS != 0;
+
+    if flags & FLAG_STRING_TABLE != 0 {
+        let len = decoder.read_count(1)?;
+        let mut strings = Vec::with_capacity(len);
+        for _ in 0..len {
+            strings.push(decoder.read_inline_string()?);
+        }
+        decoder.strings = Some(strings);
+    }
+
+    let result = _decode(&mut decoder, 0, None).and_then(|variant| {
+        if decoder.offset != bytes.len() {
+            return Err(decoder.error(ParseErrorCode::UnexpectedCharacter, "Unexpected data after the value"));
+        }
+        Ok(variant)
+    });
+    if result.is_err() {
+        for object in decoder.objects.drain(..) {
+            crate::co_object::free(object);
+        }
+    }
+    result
+}
+
+
+struct Encoder {
+    body: Vec<u8>,
+    /// Index of each string in the table, None if strings are written inline
+    string_ids: Option<HashMap<String, usize>>,
+    strings: Vec<String>,
+    refs: References,
+}
+
+
+impl Encoder {
+    fn write_len(&mut self, len: usize) {
+        _write_varint(&mut self.body, len as u64);
+    }
+
+
+    fn write_int(&mut self, value: i64) {
+        // Zigzag, so that small negative numbers stay small
+        _write_varint(&mut self.body, ((value << 1) ^ (value >> 63)) as u64);
+    }
+
+
+    fn write_reals(&mut self, values: &[real]) {
+        for value in values {
+            self.body.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+
+    fn write_string(&mut self, string: String) {
+        let Some(string_ids) = &mut self.string_ids else {
+            _write_varint(&mut self.body, string.len() as u64);
+            self.body.extend_from_slice(string.as_bytes());
+            return;
+        };
+
+        let id = match string_ids.get(&string) {
+            Some(id) => *id,
+            None => {
+                let id = self.strings.len();
+                string_ids.insert(string.clone(), id);
+                self.strings.push(string);
+                id
+            }
+        };
+        _write_varint(&mut self.body, id as u64);
+    }
+
+
+    /// Writes the anchor of the container if it is shared.
+    /// Returns false if it was already written, in which case a reference to it is written instead.
+    fn write_anchor(&mut self, variant: &Variant) -> bool {
+        match self.refs.mark(variant) {
+            Mark::None => true,
+            Mark::Anchor(id) => {
+                self.body.push(TAG_ANCHOR);
+                self.write_len(id);
+                true
+            },
+            Mark::Reference(id) => {
+                self.body.push(TAG_REFERENCE);
+                self.write_len(id);
+                false
+            }
+        }
+    }
+}
+
+
+fn _write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+
+fn _encode(variant: &Variant, encoder: &mut Encoder, depth: usize) {
+    if depth > crate::MAX_RECURSION_DEPTH {
+        godot_error!("[CerealObject] stringify max recursion depth reached");
+        encoder.body.push(TAG_STRING);
+        encoder.write_string(String::from("Max recursion depth reached"));
+        return;
+    }
+
+    // Packed arrays: tag, length, then the raw little-endian elements
+    macro_rules! encode_packed_array {
+        ($array_type:ident, $tag:ident) => {{
+            let array = $array_type::from_variant(variant);
+            encoder.body.push($tag);
+            encoder.write_len(array.len());
+            for i in 0..array.len() {
+                encoder.body.extend_from_slice(&array.get(i).to_le_bytes());
+            }
+        }};
+        ($array_type:ident, $tag:ident, |$element:ident| $write:expr) => {{
+            let array = $array_type::from_variant(variant);
+            encoder.body.push($tag);
+            encoder.write_len(array.len());
+            for i in 0..array.len() {
+                let $element = array.get(i);
+                $write;
+            }
+        }};
+    }
+
+    match variant.get_type() {
+        VariantType::Nil => encoder.body.push(TAG_NIL),
+        VariantType::Bool => encoder.body.push(if bool::from_variant(variant) { TAG_TRUE } else { TAG_FALSE }),
+        VariantType::Int => {
+            encoder.body.push(TAG_INT);
+            encoder.write_int(i64::from_variant(variant));
+        },
+        VariantType::Float => {
+            encoder.body.push(TAG_FLOAT);
+            encoder.body.extend_from_slice(&f64::from_variant(variant).to_le_bytes());
+        },
+        VariantType::String => {
+            encoder.body.push(TAG_STRING);
+            encoder.write_string(variant.to_string());
+        },
+        VariantType::StringName => {
+            encoder.body.push(TAG_STRING_NAME);
+            encoder.write_string(variant.to_string());
+        },
+        VariantType::NodePath => {
+            encoder.body.push(TAG_NODE_PATH);
+            encoder.write_string(variant.to_string());
+        },
+
+        VariantType::Vector2 => {
+            let vec2 = Vector2::from_variant(variant);
+            encoder.body.push(TAG_VECTOR2);
+            encoder.write_reals(&[vec2.x, vec2.y]);
+        },
+        VariantType::Vector2i => {
+            let vec2 = Vector2i::from_variant(variant);
+            encoder.body.push(TAG_VECTOR2I);
+            for value in [vec2.x, vec2.y] {
+                encoder.write_int(value as i64);
+            }
+        },
+        VariantType::Rect2 => {
+            let rect = Rect2::from_variant(variant);
+            encoder.body.push(TAG_RECT2);
+            encoder.write_reals(&[rect.position.x, rect.position.y, rect.size.x, rect.size.y]);
+        },
+        VariantType::Rect2i => {
+            let rect = Rect2i::from_variant(variant);
+            encoder.body.push(TAG_RECT2I);
+            for value in [rect.position.x, rect.position.y, rect.size.x, rect.size.y] {
+                encoder.write_int(value as i64);
+            }
+        },
+        VariantType::Vector3 => {
+            let vec3 = Vector3::from_variant(variant);
+            encoder.body.push(TAG_VECTOR3);
+            encoder.write_reals(&[vec3.x, vec3.y, vec3.z]);
+        },
+        VariantType::Vector3i => {
+            let vec3 = Vector3i::from_variant(variant);
+            encoder.body.push(TAG_VECTOR3I);
+            for value in [vec3.x, vec3.y, vec3.z] {
+                encoder.write_int(value as i64);
+            }
+        },
+        VariantType::Transform2D => {
+            let trans = Transform2D::from_variant(variant);
+            encoder.body.push(TAG_TRANSFORM2D);
+            encoder.write_reals(&[trans.a.x, trans.a.y, trans.b.x, trans.b.y, trans.origin.x, trans.origin.y]);
+        },
+        VariantType::Vector4 => {
+            let vec4 = Vector4::from_variant(variant);
+            encoder.body.push(TAG_VECTOR4);
+            encoder.write_reals(&[vec4.x, vec4.y, vec4.z, vec4.w]);
+        },
+        VariantType::Vector4i => {
+            let vec4 = Vector4i::from_variant(variant);
+            encoder.body.push(TAG_VECTOR4I);
+            for value in [vec4.x, vec4.y, vec4.z, vec4.w] {
+                encoder.write_int(value as i64);
+            }
+        },
+        VariantType::Plane => {
+            let plane = Plane::from_variant(variant);
+            encoder.body.push(TAG_PLANE);
+            encoder.write_reals(&[plane.normal.x, plane.normal.y, plane.normal.z, plane.d]);
+        },
+        VariantType::Quaternion => {
+            let quat = Quaternion::from_variant(variant);
+            encoder.body.push(TAG_QUATERNION);
+            encoder.write_reals(&[quat.x, quat.y, quat.z, quat.w]);
+        },
+        VariantType::Aabb => {
+            let aabb = Aabb::from_variant(variant);
+            encoder.body.push(TAG_AABB);
+            encoder.write_reals(&[aabb.position.x, aabb.position.y, aabb.position.z, aabb.size.x, aabb.size.y, aabb.size.z]);
+        },
+        VariantType::Basis => {
+            let basis = Basis::from_variant(variant);
+            encoder.body.push(TAG_BASIS);
+            for col in [basis.col_a(), basis.col_b(), basis.col_c()] {
+                encoder.write_reals(&[col.x, col.y, col.z]);
+            }
+        },
+        VariantType::Transform3D => {
+            let trans = Transform3D::from_variant(variant);
+            encoder.body.push(TAG_TRANSFORM3D);
+            for col in [trans.basis.col_a(), trans.basis.col_b(), trans.basis.col_c(), trans.origin] {
+                encoder.write_reals(&[col.x, col.y, col.z]);
+            }
+        },
+        VariantType::Projection => {
+            let proj = Projection::from_variant(variant);
+            encoder.body.push(TAG_PROJECTION);
+            for col in proj.cols {
+                encoder.write_reals(&[col.x, col.y, col.z, col.w]);
+            }
+        },
+        VariantType::Color => {
+            let color = Color::from_variant(variant);
+            encoder.body.push(TAG_COLOR);
+            for channel in [color.r, color.g, color.b, color.a] {
+                encoder.body.extend_from_slice(&channel.to_le_bytes());
+            }
+        },
+
+        VariantType::PackedByteArray => {
+            let array = PackedByteArray::from_variant(variant);
+            encoder.body.push(TAG_PACKED_BYTE_ARRAY);
+            encoder.write_len(array.len());
+            encoder.body.extend_from_slice(&array.to_vec());
+        },
+        VariantType::PackedInt32Array => encode_packed_array!(PackedInt32Array, TAG_PACKED_INT32_ARRAY),
+        VariantType::PackedInt64Array => encode_packed_array!(PackedInt64Array, TAG_PACKED_INT64_ARRAY),
+        VariantType::PackedFloat32Array => encode_packed_array!(PackedFloat32Array, TAG_PACKED_FLOAT32_ARRAY),
+        VariantType::PackedFloat64Array => encode_packed_array!(PackedFloat64Array, TAG_PACKED_FLOAT64_ARRAY),
+        VariantType::PackedStringArray => encode_packed_array!(PackedStringArray, TAG_PACKED_STRING_ARRAY,
+            |string| encoder.write_string(string.to_string())),
+        VariantType::PackedVector2Array => encode_packed_array!(PackedVector2Array, TAG_PACKED_VECTOR2_ARRAY,
+            |vec2| encoder.write_reals(&[vec2.x, vec2.y])),
+        VariantType::PackedVector3Array => encode_packed_array!(PackedVector3Array, TAG_PACKED_VECTOR3_ARRAY,
+            |vec3| encoder.write_reals(&[vec3.x, vec3.y, vec3.z])),
+        VariantType::PackedVector4Array => encode_packed_array!(PackedVector4Array, TAG_PACKED_VECTOR4_ARRAY,
+            |vec4| encoder.write_reals(&[vec4.x, vec4.y, vec4.z, vec4.w])),
+        VariantType::PackedColorArray => encode_packed_array!(PackedColorArray, TAG_PACKED_COLOR_ARRAY,
+            |color| for channel in [color.r, color.g, color.b, color.a] {
+                encoder.body.extend_from_slice(&channel.to_le_bytes());
+            }),
+
+        VariantType::Array if StringNameArray::try_from_variant(variant).is_ok() => encode_packed_array!(StringNameArray, TAG_STRING_NAME_ARRAY,
+            |name| encoder.write_string(name.to_string())),
+        VariantType::Array if NodePathArray::try_from_variant(variant).is_ok() => encode_packed_array!(NodePathArray, TAG_NODE_PATH_ARRAY,
+            |path| encoder.write_string(path.to_string())),
+        VariantType::Array => {
+            if !encoder.write_anchor(variant) {
+                return;
+            }
+            let array = crate::co_create::array_elements(variant);
+            encoder.body.push(TAG_ARRAY);
+            encoder.write_len(array.len());
+            for v in array.iter_shared() {
+                _encode(&v, encoder, depth + 1);
+            }
+        },
+        VariantType::Dictionary => {
+            if !encoder.write_anchor(variant) {
+                return;
+            }
+            let dict = Dictionary::from_variant(variant);
+            encoder.body.push(TAG_DICTIONARY);
+            encoder.write_len(dict.len());
+            for (key, value) in dict.iter_shared() {
+                _encode(&key, encoder, depth + 1);
+                _encode(&value, encoder, depth + 1);
+            }
+        },
+        VariantType::Object => {
+            // Objects are written as their class name followed by their properties
+            let Ok(object) = variant.try_to::<Gd<Object>>() else {
+                encoder.body.push(TAG_NIL);
+                return;
+            };
+            if !encoder.write_anchor(variant) {
+                return;
+            }
+            let properties = crate::co_object::storage_properties(&object);
+            encoder.body.push(TAG_OBJECT);
+            encoder.write_string(crate::co_object::class_name(&object));
+            encoder.write_len(properties.len());
+            for (name, value) in properties.iter_shared() {
+                encoder.write_string(name.to_string());
+                _encode(&value, encoder, depth + 1);
+            }
+        },
+
+        // Same as the text format, anything else is written as a string
+        _ => {
+            encoder.body.push(TAG_STRING);
+            encoder.write_string(variant.to_string());
+        }
+    }
+}
+
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    double_reals: bool,
+    /// The string table, None if strings are stored inline
+    strings: Option<Vec<String>>,
+    /// Anchored values by id, see co_parser::ParseState::anchors
+    anchors: HashMap<usize, Variant>,
+    allow_objects: bool,
+    /// Objects created so far, freed if decoding fails
+    objects: Vec<Gd<Object>>,
+}
+
+
+impl<'a> Decoder<'a> {
+    fn error(&self, code: ParseErrorCode, message: &str) -> ParseError {
+        // Binary data has no lines
+        let pos = Position { offset: self.offset, line: 0, column: 0 };
+        ParseError::new(code, pos, format!("{} (byte {})", message, self.offset))
+    }
+
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        if self.bytes.len() - self.offset < len {
+            return Err(self.error(ParseErrorCode::UnexpectedEof, "Unexpected end of data"));
+        }
+        self.offset += len;
+        Ok(&self.bytes[self.offset - len..self.offset])
+    }
+
+
+    fn read_u8(&mut self) -> Result<u8, ParseError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+
+    fn read_varint(&mut self) -> Result<u64, ParseError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_u8()?;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(self.error(ParseErrorCode::MalformedNumber, "Varint is too long"))
+    }
+
+
+    fn read_int(&mut self) -> Result<i64, ParseError> {
+        let value = self.read_varint()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+
+    fn read_i32(&mut self) -> Result<i32, ParseError> {
+        let value = self.read_int()?;
+        i32::try_from(value).map_err(|_| self.error(ParseErrorCode::MalformedNumber, &format!("{} does not fit in an Int32", value)))
+    }
+
+
+    /// Reads the length of a collection, checking that the data can hold that many elements of the given size
+    fn read_count(&mut self, element_size: usize) -> Result<usize, ParseError> {
+        let len = self.read_varint()?;
+        match usize::try_from(len) {
+            Ok(len) if len.saturating_mul(element_size) <= self.bytes.len() - self.offset => Ok(len),
+            _ => Err(self.error(ParseErrorCode::UnexpectedEof, &format!("Not enough data for {} elements", len)))
+        }
+    }
+
+
+    fn read_f32(&mut self) -> Result<f32, ParseError> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.read_bytes(4)?);
+        Ok(f32::from_le_bytes(bytes))
+    }
+
+
+    fn read_f64(&mut self) -> Result<f64, ParseError> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.read_bytes(8)?);
+        Ok(f64::from_le_bytes(bytes))
+    }
+
+
+    fn real_size(&self) -> usize {
+        if self.double_reals { 8 } else { 4 }
+    }
+
+
+    /// Reads reals with the precision they were written with, and converts them to the engine's real_t
+    fn read_reals<const N: usize>(&mut self) -> Result<[real; N], ParseError> {
+        let mut reals = [0 as real; N];
+        for value in reals.iter_mut() {
+            *value = if self.double_reals { self.read_f64()? as real } else { self.read_f32()? as real };
+        }
+        Ok(reals)
+    }
+
+
+    fn read_ints<const N: usize>(&mut self) -> Result<[i32; N], ParseError> {
+        let mut ints = [0; N];
+        for value in ints.iter_mut() {
+            *value = self.read_i32()?;
+        }
+        Ok(ints)
+    }
+
+
+    fn read_color(&mut self) -> Result<Color, ParseError> {
+        Ok(Color::from_rgba(self.read_f32()?, self.read_f32()?, self.read_f32()?, self.read_f32()?))
+    }
+
+
+    fn read_inline_string(&mut self) -> Result<String, ParseError> {
+        let len = self.read_count(1)?;
+        let start = self.offset;
+        match std::str::from_utf8(self.read_bytes(len)?) {
+            Ok(string) => Ok(String::from(string)),
+            Err(_) => {
+                self.offset = start;
+                Err(self.error(ParseErrorCode::MalformedString, "Invalid UTF-8 string"))
+            }
+        }
+    }
+
+
+    fn read_string(&mut self) -> Result<String, ParseError> {
+        if self.strings.is_none() {
+            return self.read_inline_string();
+        }
+
+        let id = self.read_varint()?;
+        let string = self.strings.as_ref()
+            .and_then(|strings| strings.get(usize::try_from(id).ok()?))
+            .cloned();
+        string.ok_or_else(|| self.error(ParseErrorCode::MalformedString, &format!("Unknown string {}", id)))
+    }
+}
+
+
+fn _decode(decoder: &mut Decoder, depth: usize, anchor: Option<usize>) -> Result<Variant, ParseError> {
+    if depth > crate::MAX_RECURSION_DEPTH {
+        return Err(decoder.error(ParseErrorCode::MaxDepth, "Reached max recursion depth"));
+    }
+
+    // Packed arrays of numbers: length, then the raw little-endian elements
+    macro_rules! decode_packed_array {
+        ($array_type:ident, $element:ty) => {{
+            let size = std::mem::size_of::<$element>();
+            let len = decoder.read_count(size)?;
+            let values: Vec<$element> = decoder.read_bytes(len * size)?
+                .chunks_exact(size)
+                .map(|chunk| <$element>::from_le_bytes(chunk.try_into().unwrap()))
+                .collect();
+            Variant::from($array_type::from(values.as_slice()))
+        }};
+        ($array_type:ident, $element_size:expr, || $read:expr) => {{
+            let len = decoder.read_count($element_size)?;
+            let mut values = Vec::with_capacity(len);
+            for _ in 0..len {
+                values.push($read);
+            }
+            Variant::from($array_type::from(values.as_slice()))
+        }};
+    }
+
+    let tag_offset = decoder.offset;
+    let variant = match decoder.read_u8()? {
+        TAG_ANCHOR => {
+            if anchor.is_some() {
+                decoder.offset = tag_offset;
+                return Err(decoder.error(ParseErrorCode::UnexpectedToken, "A value cannot have two anchors"));
+            }
+            let id = decoder.read_varint()? as usize;
+            return _decode(decoder, depth, Some(id));
+        },
+        TAG_REFERENCE => {
+            let id = decoder.read_varint()? as usize;
+            return match decoder.anchors.get(&id) {
+                Some(variant) => Ok(variant.clone()),
+                None => Err(decoder.error(ParseErrorCode::UnknownReference, &format!("Unknown reference {}", id)))
+            };
+        },
+
+        TAG_NIL => Variant::nil(),
+        TAG_FALSE => Variant::from(false),
+        TAG_TRUE => Variant::from(true),
+        TAG_INT => Variant::from(decoder.read_int()?),
+        TAG_FLOAT => Variant::from(decoder.read_f64()?),
+        TAG_STRING => Variant::from(GString::from(decoder.read_string()?)),
+        TAG_STRING_NAME => Variant::from(StringName::from(decoder.read_string()?.as_str())),
+        TAG_NODE_PATH => Variant::from(NodePath::from(decoder.read_string()?.as_str())),
+
+        TAG_VECTOR2 => {
+            let [x, y] = decoder.read_reals()?;
+            Variant::from(Vector2::new(x, y))
+        },
+        TAG_VECTOR2I => {
+            let [x, y] = decoder.read_ints()?;
+            Variant::from(Vector2i::new(x, y))
+        },
+        TAG_RECT2 => {
+            let [x, y, w, h] = decoder.read_reals()?;
+            Variant::from(Rect2::new(Vector2::new(x, y), Vector2::new(w, h)))
+        },
+        TAG_RECT2I => {
+            let [x, y, w, h] = decoder.read_ints()?;
+            Variant::from(Rect2i::new(Vector2i::new(x, y), Vector2i::new(w, h)))
+        },
+        TAG_VECTOR3 => {
+            let [x, y, z] = decoder.read_reals()?;
+            Variant::from(Vector3::new(x, y, z))
+        },
+        TAG_VECTOR3I => {
+            let [x, y, z] = decoder.read_ints()?;
+            Variant::from(Vector3i::new(x, y, z))
+        },
+        TAG_TRANSFORM2D => {
+            let [x0, x1, y0, y1, ox, oy] = decoder.read_reals()?;
+            Variant::from(Transform2D::from_cols(Vector2::new(x0, x1), Vector2::new(y0, y1), Vector2::new(ox, oy)))
+        },
+        TAG_VECTOR4 => {
+            let [x, y, z, w] = decoder.read_reals()?;
+            Variant::from(Vector4::new(x, y, z, w))
+        },
+        TAG_VECTOR4I => {
+            let [x, y, z, w] = decoder.read_ints()?;
+            Variant::from(Vector4i::new(x, y, z, w))
+        },
+        TAG_PLANE => {
+            let [x, y, z, d] = decoder.read_reals()?;
+            Variant::from(Plane { normal: Vector3::new(x, y, z), d })
+        },
+        TAG_QUATERNION => {
+            let [x, y, z, w] = decoder.read_reals()?;
+            Variant::from(Quaternion::new(x, y, z, w))
+        },
+        TAG_AABB => {
+            let [x, y, z, w, h, l] = decoder.read_reals()?;
+            Variant::from(Aabb::new(Vector3::new(x, y, z), Vector3::new(w, h, l)))
+        },
+        TAG_BASIS => {
+            let [x0, x1, x2, y0, y1, y2, z0, z1, z2] = decoder.read_reals()?;
+            Variant::from(Basis::from_cols(Vector3::new(x0, x1, x2), Vector3::new(y0, y1, y2), Vector3::new(z0, z1, z2)))
+        },
+        TAG_TRANSFORM3D => {
+            let [x0, x1, x2, y0, y1, y2, z0, z1, z2, ox, oy, oz] = decoder.read_reals()?;
+            Variant::from(Transform3D::from_cols(
+                Vector3::new(x0, x1, x2),
+                Vector3::new(y0, y1, y2),
+                Vector3::new(z0, z1, z2),
+                Vector3::new(ox, oy, oz)
+            ))
+        },
+        TAG_PROJECTION => {
+            let [
+                x0, x1, x2, x3,
+                y0, y1, y2, y3,
+                z0, z1, z2, z3,
+                w0, w1, w2, w3
+            ] = decoder.read_reals()?;
+            Variant::from(Projection::from_cols(
+                Vector4::new(x0, x1, x2, x3),
+                Vector4::new(y0, y1, y2, y3),
+                Vector4::new(z0, z1, z2, z3),
+                Vector4::new(w0, w1, w2, w3)
+            ))
+        },
+        TAG_COLOR => Variant::from(decoder.read_color()?),
+
+        TAG_PACKED_BYTE_ARRAY => {
+            let len = decoder.read_count(1)?;
+            Variant::from(PackedByteArray::from(decoder.read_bytes(len)?))
+        },
+        TAG_PACKED_INT32_ARRAY => decode_packed_array!(PackedInt32Array, i32),
+        TAG_PACKED_INT64_ARRAY => decode_packed_array!(PackedInt64Array, i64),
+        TAG_PACKED_FLOAT32_ARRAY => decode_packed_array!(PackedFloat32Array, f32),
+        TAG_PACKED_FLOAT64_ARRAY => decode_packed_array!(PackedFloat64Array, f64),
+        TAG_PACKED_STRING_ARRAY => decode_packed_array!(PackedStringArray, 1,
+            || GString::from(decoder.read_string()?)),
+        TAG_PACKED_VECTOR2_ARRAY => decode_packed_array!(PackedVector2Array, 2 * decoder.real_size(), || {
+            let [x, y] = decoder.read_reals()?;
+            Vector2::new(x, y)
+        }),
+        TAG_PACKED_VECTOR3_ARRAY => decode_packed_array!(PackedVector3Array, 3 * decoder.real_size(), || {
+            let [x, y, z] = decoder.read_reals()?;
+            Vector3::new(x, y, z)
+        }),
+        TAG_PACKED_VECTOR4_ARRAY => decode_packed_array!(PackedVector4Array, 4 * decoder.real_size(), || {
+            let [x, y, z, w] = decoder.read_reals()?;
+            Vector4::new(x, y, z, w)
+        }),
+        TAG_PACKED_COLOR_ARRAY => decode_packed_array!(PackedColorArray, 16, || decoder.read_color()?),
+
+        TAG_STRING_NAME_ARRAY => {
+            let len = decoder.read_count(1)?;
+            let mut array = StringNameArray::new();
+            for _ in 0..len {
+                array.push(StringName::from(decoder.read_string()?.as_str()));
+            }
+            Variant::from(array)
+        },
+        TAG_NODE_PATH_ARRAY => {
+            let len = decoder.read_count(1)?;
+            let mut array = NodePathArray::new();
+            for _ in 0..len {
+                array.push(NodePath::from(decoder.read_string()?.as_str()));
+            }
+            Variant::from(array)
+        },
+        TAG_ARRAY => {
+            let len = decoder.read_count(1)?;
+            let mut array = Array::<Variant>::new();
+
+            if let Some(id) = anchor {
+                decoder.anchors.insert(id, Variant::from(array.clone()));
+            }
+            for _ in 0..len {
+                array.push(_decode(decoder, depth + 1, None)?);
+            }
+            Variant::from(array)
+        },
+        TAG_DICTIONARY => {
+            let len = decoder.read_count(2)?;
+            let mut dict = Dictionary::new();
+
+            if let Some(id) = anchor {
+                decoder.anchors.insert(id, Variant::from(dict.clone()));
+            }
+            for _ in 0..len {
+                let key = _decode(decoder, depth + 1, None)?;
+                let value = _decode(decoder, depth + 1, None)?;
+                dict.insert(key, value);
+            }
+            Variant::from(dict)
+        },
+        TAG_OBJECT => {
+            let class_name = decoder.read_string()?;
+            if !decoder.allow_objects {
+                decoder.offset = tag_offset;
+                return Err(decoder.error(ParseErrorCode::ObjectNotAllowed, &format!("Objects are not allowed: {}", class_name)));
+            }
+            let Some(mut object) = crate::co_object::instantiate(&class_name) else {
+                return Err(decoder.error(ParseErrorCode::UnknownClass, &format!("Cannot instantiate class {}", class_name)));
+            };
+            decoder.objects.push(object.clone());
+
+            if let Some(id) = anchor {
+                decoder.anchors.insert(id, Variant::from(object.clone()));
+            }
+            let properties = _decode_properties(decoder, depth)?;
+            if let Err(err) = crate::co_object::set_properties(&mut object, &properties) {
+                decoder.offset = tag_offset;
+                return Err(decoder.error(ParseErrorCode::UnknownClass, &err));
+            }
+            Variant::from(object)
+        },
+
+        tag => {
+            decoder.offset = tag_offset;
+            return Err(decoder.error(ParseErrorCode::UnsupportedType, &format!("Unknown type tag {}", tag)));
+        }
+    };
+
+    if let Some(id) = anchor {
+        decoder.anchors.insert(id, variant.clone());
+    }
+    Ok(variant)
+}
+
+
+/// Reads the properties of an object, each being a name followed by a value
+fn _decode_properties(decoder: &mut Decoder, depth: usize) -> Result<Dictionary, ParseError> {
+    let len = decoder.read_count(2)?;
+    let mut properties = Dictionary::new();
+    for _ in 0..len {
+        let name = decoder.read_string()?;
+        properties.insert(GString::from(name), _decode(decoder, depth + 1, None)?);
+    }
+    Ok(properties)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn decode_error(body: &[u8]) -> ParseError {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[VERSION, 0]);
+        bytes.extend_from_slice(body);
+        match decode(&bytes, false) {
+            Ok(_) => panic!("{:?} should not decode", body),
+            Err(err) => err
+        }
+    }
+
+
+    #[test]
+    fn invalid_header() {
+        let err = decode(b"{ a: 1 }", false).err().unwrap();
+        assert_eq!(err.code, ParseErrorCode::InvalidHeader);
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[VERSION + 1, 0, TAG_NIL]);
+        assert_eq!(decode(&bytes, false).err().unwrap().code, ParseErrorCode::InvalidHeader);
+    }
+
+
+    #[test]
+    fn truncated_data() {
+        assert_eq!(decode_error(&[]).code, ParseErrorCode::UnexpectedEof);
+        // An int whose varint never ends
+        assert_eq!(decode_error(&[TAG_INT, 0x80, 0x80]).code, ParseErrorCode::UnexpectedEof);
+        // A string announcing more bytes than there are
+        let err = decode_error(&[TAG_STRING, 10, b'a', b'b']);
+        assert_eq!(err.code, ParseErrorCode::UnexpectedEof);
+        assert_eq!(err.offset, 8);
+        // A packed array announcing more elements than could fit
+        assert_eq!(decode_error(&[TAG_PACKED_INT64_ARRAY, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F]).code, ParseErrorCode::UnexpectedEof);
+    }
+
+
+    #[test]
+    fn malformed_data() {
+        let err = decode_error(&[200]);
+        assert_eq!(err.code, ParseErrorCode::UnsupportedType);
+        assert_eq!(err.offset, 6);
+
+        // An int whose varint is longer than 64 bits
+        let mut body = vec![TAG_INT];
+        body.extend_from_slice(&[0x80; 10]);
+        assert_eq!(decode_error(&body).code, ParseErrorCode::MalformedNumber);
+        assert_eq!(decode_error(&[TAG_STRING, 2, 0xC3, 0x28]).code, ParseErrorCode::MalformedString);
+        assert_eq!(decode_error(&[TAG_REFERENCE, 1]).code, ParseErrorCode::UnknownReference);
+    }
+
+
+    #[test]
+    fn objects_not_allowed() {
+        let err = decode_error(&[TAG_OBJECT, 4, b'N', b'o', b'd', b'e', 0]);
+        assert_eq!(err.code, ParseErrorCode::ObjectNotAllowed);
+        assert_eq!(err.offset, 6);
+    }
+
+
+    #[test]
+    fn repeated_anchors() {
+        let body: Vec<u8> = [TAG_ANCHOR, 0].repeat(100_000);
+        let err = decode_error(&body);
+        assert_eq!(err.code, ParseErrorCode::UnexpectedToken);
+        assert_eq!(err.offset, 8);
+    }
+}
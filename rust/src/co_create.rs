@@ -1,149 +1,519 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
 use godot::prelude::*;
+use godot::engine::utilities;
+
+use crate::co_pretty::{self, Doc};
+
+
+/// How the variant is written
+#[derive(Clone)]
+pub struct StringifyOptions {
+    /// Used to indent nested values, everything is written on a single line if empty
+    pub indent: String,
+    /// Writes dictionary keys in order instead of insertion order
+    pub sort_keys: bool,
+    /// Target width of the lines. Collections that fit are kept on a single line, and the elements
+    /// of packed arrays are packed on as few lines as possible. 0 puts every element on its own line.
+    pub max_inline_width: usize,
+    /// Adds a comma after the last element of collections written on several lines
+    pub trailing_commas: bool,
+    /// Either " or '
+    pub quote: char,
+    /// Number of decimals of floats, None for the shortest text that parses back to the same float
+    pub float_precision: Option<usize>,
+    /// Writes the type of numbers after them, e.g. 1.5f or 2i
+    pub type_suffixes: bool,
+    /// Writes the elements of PackedByteArrays as hexadecimal numbers, e.g. 0xFF
+    pub hex_bytes: bool,
+    /// Writes every integer as a hexadecimal number
+    pub hex_ints: bool,
+    /// Writes the integer values of these dictionary keys and object properties as hexadecimal
+    /// numbers, e.g. collision_layer: 0x5
+    pub hex_keys: Vec<String>,
+    /// Sorts keys by their text rather than their value, so that the order does not depend on
+    /// anything but the keys themselves
+    pub canonical: bool,
+}
+
+
+impl Default for StringifyOptions {
+    fn default() -> Self {
+        StringifyOptions {
+            indent: String::from("    "),
+            sort_keys: false,
+            max_inline_width: 100,
+            trailing_commas: false,
+            quote: '"',
+            float_precision: None,
+            type_suffixes: true,
+            hex_bytes: false,
+            hex_ints: false,
+            hex_keys: Vec::new(),
+            canonical: false
+        }
+    }
+}
+
+
+impl StringifyOptions {
+    /// Options giving the exact same text for equal values: no whitespace, keys sorted by their
+    /// text, floats with the shortest exact text and type suffixes, double quoted strings
+    pub fn canonical() -> Self {
+        StringifyOptions {
+            indent: String::new(),
+            sort_keys: true,
+            max_inline_width: 0,
+            trailing_commas: false,
+            quote: '"',
+            float_precision: None,
+            type_suffixes: true,
+            hex_bytes: false,
+            hex_ints: false,
+            hex_keys: Vec::new(),
+            canonical: true
+        }
+    }
+
+
+    /// Reads the options from a dictionary, the missing ones keep their default value.
+    /// The other options are ignored if canonical is set.
+    pub fn from_dictionary(dict: &Dictionary) -> Self {
+        if dict.get("canonical").is_some_and(|canonical| canonical.booleanize()) {
+            return StringifyOptions::canonical();
+        }
+
+        let mut options = StringifyOptions::default();
+        if let Some(indent) = dict.get("indent") {
+            options.indent = indent.to_string();
+        }
+        if let Some(sort_keys) = dict.get("sort_keys") {
+            options.sort_keys = sort_keys.booleanize();
+        }
+        if let Some(width) = dict.get("max_inline_width") {
+            options.max_inline_width = width.try_to::<i64>().map_or(0, |width| width.max(0) as usize);
+        }
+        if let Some(trailing_commas) = dict.get("trailing_commas") {
+            options.trailing_commas = trailing_commas.booleanize();
+        }
+        if let Some(quote_style) = dict.get("quote_style") {
+            options.quote = if quote_style.to_string() == "single" { '\'' } else { '"' };
+        }
+        if let Some(precision) = dict.get("float_precision") {
+            // Negative values stand for the shortest text
+            options.float_precision = precision.try_to::<i64>().ok().and_then(|precision| usize::try_from(precision).ok());
+        }
+        if let Some(type_suffixes) = dict.get("type_suffixes") {
+            options.type_suffixes = type_suffixes.booleanize();
+        }
+        if let Some(hex_bytes) = dict.get("hex_bytes") {
+            options.hex_bytes = hex_bytes.booleanize();
+        }
+        if let Some(hex_ints) = dict.get("hex_ints") {
+            options.hex_ints = hex_ints.booleanize();
+        }
+        if let Some(hex_keys) = dict.get("hex_keys") {
+            // Either an Array or a PackedStringArray
+            options.hex_keys = match hex_keys.get_type() {
+                VariantType::Array => array_elements(&hex_keys).iter_shared().map(|key| key.to_string()).collect(),
+                VariantType::PackedStringArray => PackedStringArray::from_variant(&hex_keys).to_vec().iter().map(|key| key.to_string()).collect(),
+                _ => Vec::new()
+            };
+        }
+        options
+    }
+}
 
 
 pub fn stringify_raw(variant: &Variant) -> GString {
-    _stringify(variant, "", 0)
+    stringify_with_indent(variant, "")
 }
 
 
 pub fn stringify(variant: &Variant) -> GString {
-    _stringify(variant, "    ", 0)
+    stringify_with_options(variant, &StringifyOptions::default())
+}
+
+
+/// Stringifies the variant using the given string to indent nested values
+pub fn stringify_with_indent(variant: &Variant, indent: &str) -> GString {
+    stringify_with_options(variant, &StringifyOptions { indent: String::from(indent), ..StringifyOptions::default() })
 }
 
 
-fn _stringify(variant: &Variant, indent: &str, curr_indent: usize) -> GString {
+pub fn stringify_with_options(variant: &Variant, options: &StringifyOptions) -> GString {
+    let mut refs = References::with_options(variant, options);
+    if options.indent.is_empty() || options.max_inline_width == 0 {
+        return _stringify(variant, options, 0, &mut refs);
+    }
+
+    let doc = _to_doc(variant, options, 0, &mut refs);
+    GString::from(co_pretty::render(&doc, &options.indent, options.max_inline_width))
+}
+
+
+/// Stringifies the variant so that equal values always give the same text, see StringifyOptions::canonical
+pub fn stringify_canonical(variant: &Variant) -> GString {
+    stringify_with_options(variant, &StringifyOptions::canonical())
+}
+
+
+/// Stringifies the variant as a dictionary key
+pub fn stringify_key(key: &Variant) -> GString {
+    stringify_key_with_options(key, &StringifyOptions::default())
+}
+
+
+pub fn stringify_key_with_options(key: &Variant, options: &StringifyOptions) -> GString {
+    GString::from(_stringify_key(key, options, &mut References::with_options(key, options)))
+}
+
+
+type StringNameArray = Array<StringName>;
+type NodePathArray = Array<NodePath>;
+
+
+/// Arrays, dictionaries and objects that appear more than once in the stringified variant.
+/// They are written with an anchor (&id1) the first time, and with a reference (*id1) afterwards.
+/// In canonical mode, only the values closing a cycle are anchored: the other shared values are
+/// written in full wherever they appear, so that the text does not depend on how values are shared.
+pub(crate) struct References {
+    /// The shared values by _identity_key, with the id of their anchor once written
+    shared: HashMap<(i32, i64), Vec<(Variant, Option<usize>)>>,
+    next_id: usize,
+}
+
+
+pub(crate) enum Mark {
+    None,
+    Anchor(usize),
+    Reference(usize),
+}
+
+
+impl References {
+    /// Finds the shared values, walking dictionaries in insertion order
+    pub(crate) fn new(variant: &Variant) -> Self {
+        Self::with_options(variant, &StringifyOptions::default())
+    }
+
+
+    /// Finds the shared values, walking dictionaries in the order the options write them
+    pub(crate) fn with_options(variant: &Variant, options: &StringifyOptions) -> Self {
+        let mut seen = HashMap::<(i32, i64), Vec<Seen>>::new();
+        _find_shared(variant, options, &mut seen, 0);
+
+        let shared = seen.into_iter()
+            .map(|(key, candidates)| {
+                let shared: Vec<_> = candidates.into_iter()
+                    .filter(|seen| seen.is_shared)
+                    .map(|seen| (seen.variant, None))
+                    .collect();
+                (key, shared)
+            })
+            .filter(|(_, shared)| !shared.is_empty())
+            .collect();
+
+        References { shared, next_id: 1 }
+    }
+
+
+    /// Tells whether the variant must be written with an anchor, a reference or as is
+    pub(crate) fn mark(&mut self, variant: &Variant) -> Mark {
+        let Some(candidates) = _identity_key(variant).and_then(|key| self.shared.get_mut(&key)) else {
+            return Mark::None;
+        };
+
+        for (shared, id) in candidates.iter_mut() {
+            if !_is_same(shared, variant) {
+                continue;
+            }
+
+            return match id {
+                Some(id) => Mark::Reference(*id),
+                None => {
+                    *id = Some(self.next_id);
+                    self.next_id += 1;
+                    Mark::Anchor(self.next_id - 1)
+                }
+            };
+        }
+
+        Mark::None
+    }
+}
+
+
+/// A value met while looking for shared values
+struct Seen {
+    variant: Variant,
+    is_shared: bool,
+    /// Whether the value is being walked, meeting it again means it is in a cycle
+    is_open: bool,
+}
+
+
+/// Walks the variant in the same order as _stringify, marking the values met more than once
+fn _find_shared(variant: &Variant, options: &StringifyOptions, seen: &mut HashMap<(i32, i64), Vec<Seen>>, depth: usize) {
+    if depth > crate::MAX_RECURSION_DEPTH {
+        return;
+    }
+
+    let Some(key) = _identity_key(variant) else { return };
+
+    // The key only narrows down the candidates, the identity check is done by _is_same
+    let candidates = seen.entry(key).or_default();
+    if let Some(candidate) = candidates.iter_mut().find(|candidate| _is_same(&candidate.variant, variant)) {
+        if !options.canonical || candidate.is_open {
+            candidate.is_shared = true;
+        }
+        return;
+    }
+    let index = candidates.len();
+    candidates.push(Seen { variant: variant.clone(), is_shared: false, is_open: true });
+
+    match variant.get_type() {
+        VariantType::Array => {
+            for v in array_elements(variant).iter_shared() {
+                _find_shared(&v, options, seen, depth + 1);
+            }
+        },
+        VariantType::Dictionary => _find_shared_in_dict(&Dictionary::from_variant(variant), options, seen, depth),
+        _ => {
+            if let Ok(object) = variant.try_to::<Gd<Object>>() {
+                _find_shared_in_dict(&crate::co_object::storage_properties(&object), options, seen, depth);
+            }
+        }
+    }
+
+    seen.get_mut(&key).unwrap()[index].is_open = false;
+}
+
+
+fn _find_shared_in_dict(dict: &Dictionary, options: &StringifyOptions, seen: &mut HashMap<(i32, i64), Vec<Seen>>, depth: usize) {
+    for key in _keys(dict, options) {
+        _find_shared(&key, options, seen, depth + 1);
+        _find_shared(&dict.get(key).unwrap(), options, seen, depth + 1);
+    }
+}
+
+
+/// Narrows down which values can be the same as the variant, without looking into it: hashing
+/// a container hashes its whole content, which is slow and never ends on cyclic containers.
+/// Returns None for values that cannot be shared.
+fn _identity_key(variant: &Variant) -> Option<(i32, i64)> {
+    let key = match variant.get_type() {
+        VariantType::Array => Array::<Variant>::try_from_variant(variant).map_or(-1, |array| array.len() as i64),
+        VariantType::Dictionary => Dictionary::from_variant(variant).len() as i64,
+        VariantType::Object => variant.try_to::<Gd<Object>>().map_or(0, |object| object.instance_id().to_i64()),
+        _ => return None
+    };
+    Some((variant.get_type() as i32, key))
+}
+
+
+/// Returns the elements of an array, typed or not: Array::<Variant>::from_variant panics on typed arrays.
+/// The elements of a typed array are copied to an untyped one, containers in it are still shared.
+pub(crate) fn array_elements(variant: &Variant) -> Array<Variant> {
+    match Array::<Variant>::try_from_variant(variant) {
+        Ok(array) => array,
+        Err(_) => {
+            let elements = Array::<Variant>::new();
+            elements.to_variant().call("assign", &[variant.clone()]);
+            elements
+        }
+    }
+}
+
+
+fn _is_same(a: &Variant, b: &Variant) -> bool {
+    a.get_type() == b.get_type() && utilities::is_same(a.clone(), b.clone())
+}
+
+
+/// Returns the anchor to write before the variant, or the reference to write instead of it
+fn _anchor(variant: &Variant, refs: &mut References) -> Result<String, GString> {
+    match refs.mark(variant) {
+        Mark::None => Ok(String::new()),
+        Mark::Anchor(id) => Ok(format!("&id{} ", id)),
+        Mark::Reference(id) => Err(GString::from(format!("*id{}", id)))
+    }
+}
+
+
+fn _stringify(variant: &Variant, options: &StringifyOptions, curr_indent: usize, refs: &mut References) -> GString {
     if curr_indent > crate::MAX_RECURSION_DEPTH {
         godot_error!("[CerealObject] stringify max recursion depth reached");
         return GString::from("\"Max recursion depth reached\"");
     }
 
+    let indent = options.indent.as_str();
     let colon = if indent.is_empty() { ":" } else { ": " };
     let end_statement = if indent.is_empty() { "" } else { "\n" };
+    let trailing_comma = if indent.is_empty() || !options.trailing_commas { "" } else { "," };
+    // The arguments of constructors are numbers, canonical text writes them without spaces
+    let constructor = |string: String| GString::from(if options.canonical { string.replace(' ', "") } else { string });
 
-    // Macro for packed string arrays
-    macro_rules! stringify_packed_array {
-        ($array_type:ident, $prefix:literal) => {{
-            let array = $array_type::from_variant(variant);
-            let mut string = String::from("[");
-            string += $prefix;
-
-            for i in 0..array.len() {
-                string += ",";
-                string += end_statement;
-                string += &_make_indent(indent, curr_indent + 1);
-                string += &_stringify(&Variant::from(array.get(i)), indent, curr_indent + 1).to_string();
-            }
+    if let Some((marker, elements)) = _packed_array(variant, options, refs) {
+        let mut string = String::from("[");
+        string += marker;
 
+        for element in &elements {
+            string += ",";
             string += end_statement;
-            string += &_make_indent(indent, curr_indent);
-            string += "]";
-            return string.into_godot();
-        }};
-    }
+            string += &_make_indent(indent, curr_indent + 1);
+            string += element;
+        }
 
+        if !elements.is_empty() {
+            string += trailing_comma;
+        }
+        string += end_statement;
+        string += &_make_indent(indent, curr_indent);
+        string += "]";
+        return string.into_godot();
+    }
 
 
     match variant.get_type() {
         VariantType::Nil => GString::from("null"),
-        
-        VariantType::PackedFloat32Array => stringify_packed_array!(PackedFloat32Array, "f32"),
-        VariantType::PackedFloat64Array => stringify_packed_array!(PackedFloat64Array, "f64"),
-        VariantType::PackedInt32Array => stringify_packed_array!(PackedInt32Array, "i32"),
-        VariantType::PackedInt64Array => stringify_packed_array!(PackedInt64Array, "i64"),
-        VariantType::PackedByteArray => stringify_packed_array!(PackedByteArray, "B"),
-        VariantType::PackedStringArray => stringify_packed_array!(PackedStringArray, "String"),
 
         VariantType::Rect2 => {
             let rect = Rect2::from_variant(variant);
-            return GString::from(format!(
-                "Rect2({}f, {}f; {}f, {}f)", rect.position.x, rect.position.y,
-                rect.size.x, rect.size.y
+            return constructor(format!(
+                "Rect2({}, {}; {}, {})", _format_float(rect.position.x, "f", options), _format_float(rect.position.y, "f", options),
+                _format_float(rect.size.x, "f", options), _format_float(rect.size.y, "f", options)
                 )
             );
         }
         VariantType::Rect2i => {
             let rect = Rect2i::from_variant(variant);
-            return GString::from(format!(
-                "Rect2i({}i, {}i; {}i, {}i)", rect.position.x, rect.position.y,
-                rect.size.x, rect.size.y
+            return constructor(format!(
+                "Rect2i({}, {}; {}, {})", _format_int(rect.position.x, options), _format_int(rect.position.y, options),
+                _format_int(rect.size.x, options), _format_int(rect.size.y, options)
                 )
             );
         }
         VariantType::Aabb => {
             let aabb = Aabb::from_variant(variant);
-            return GString::from(format!(
-                "AABB({}f, {}f, {}f; {}f, {}f, {}f)", aabb.position.x, aabb.position.y, aabb.position.z,
-                aabb.size.x, aabb.size.y, aabb.size.z
+            return constructor(format!(
+                "AABB({}, {}, {}; {}, {}, {})", _format_float(aabb.position.x, "f", options), _format_float(aabb.position.y, "f", options), _format_float(aabb.position.z, "f", options),
+                _format_float(aabb.size.x, "f", options), _format_float(aabb.size.y, "f", options), _format_float(aabb.size.z, "f", options)
                 )
             );
         }
 
         VariantType::Color => {
             let color = Color::from_variant(variant);
-            return GString::from(format!(
-                "Color({}, {}, {}, {})", color.r8(), color.g8(), color.b8(), color.a8()
+            let color8 = Color::from_rgba8(color.r8(), color.g8(), color.b8(), color.a8());
+            if color8 == color {
+                return constructor(format!(
+                    "Color({}, {}, {}, {})", color.r8(), color.g8(), color.b8(), color.a8()
+                ));
+            }
+            // Not representable with bytes, write the channels as they are
+            return constructor(format!(
+                "Color({}, {}, {}, {})",
+                _format_float(color.r, "f32", options), _format_float(color.g, "f32", options), _format_float(color.b, "f32", options), _format_float(color.a, "f32", options)
             ));
         }
         VariantType::Vector2 => {
             let vec2 = Vector2::from_variant(variant);
-            return GString::from(format!(
-                "Vector2({}f, {}f)", vec2.x, vec2.y
+            return constructor(format!(
+                "Vector2({}, {})", _format_float(vec2.x, "f", options), _format_float(vec2.y, "f", options)
             ));
         }
         VariantType::Vector3 => {
             let vec3 = Vector3::from_variant(variant);
-            return GString::from(format!(
-                "Vector3({}f, {}f, {}f)", vec3.x, vec3.y, vec3.z
+            return constructor(format!(
+                "Vector3({}, {}, {})", _format_float(vec3.x, "f", options), _format_float(vec3.y, "f", options), _format_float(vec3.z, "f", options)
             ));
         }
         VariantType::Vector4 => {
             let vec4 = Vector4::from_variant(variant);
-            return GString::from(format!(
-                "Vector4({}f, {}f, {}f, {}f)", vec4.x, vec4.y, vec4.z, vec4.w
+            return constructor(format!(
+                "Vector4({}, {}, {}, {})", _format_float(vec4.x, "f", options), _format_float(vec4.y, "f", options), _format_float(vec4.z, "f", options), _format_float(vec4.w, "f", options)
             ));
         }
 
         VariantType::Vector2i => {
             let vec2 = Vector2i::from_variant(variant);
-            return GString::from(format!(
-                "Vector2i({}i, {}i)", vec2.x, vec2.y
+            return constructor(format!(
+                "Vector2i({}, {})", _format_int(vec2.x, options), _format_int(vec2.y, options)
             ));
         }
         VariantType::Vector3i => {
             let vec3 = Vector3i::from_variant(variant);
-            return GString::from(format!(
-                "Vector3i({}i, {}i, {}i)", vec3.x, vec3.y, vec3.z
+            return constructor(format!(
+                "Vector3i({}, {}, {})", _format_int(vec3.x, options), _format_int(vec3.y, options), _format_int(vec3.z, options)
             ));
         }
         VariantType::Vector4i => {
             let vec4 = Vector4i::from_variant(variant);
-            return GString::from(format!(
-                "Vector4i({}i, {}i, {}i, {}i)", vec4.x, vec4.y, vec4.z, vec4.w
+            return constructor(format!(
+                "Vector4i({}, {}, {}, {})", _format_int(vec4.x, options), _format_int(vec4.y, options), _format_int(vec4.z, options), _format_int(vec4.w, options)
             ));
         }
 
         VariantType::Transform2D => {
             let trans = Transform2D::from_variant(variant);
-            return GString::from(format!(
-                "Transform2D({}f, {}f; {}f, {}f; {}f, {}f)", trans.a.x, trans.a.y,
-                trans.b.x, trans.b.y,
-                trans.origin.x, trans.origin.y
+            return constructor(format!(
+                "Transform2D({}, {}; {}, {}; {}, {})", _format_float(trans.a.x, "f", options), _format_float(trans.a.y, "f", options),
+                _format_float(trans.b.x, "f", options), _format_float(trans.b.y, "f", options),
+                _format_float(trans.origin.x, "f", options), _format_float(trans.origin.y, "f", options)
             ))
         }
         VariantType::Transform3D => {
             let trans = Transform3D::from_variant(variant);
-            return GString::from(format!(
-                "Transform3D({}f, {}f, {}f; {}f, {}f, {}f; {}f, {}f, {}f; {}f, {}f, {}f)",
-                trans.basis.col_a().x, trans.basis.col_a().y, trans.basis.col_a().z,
-                trans.basis.col_b().x, trans.basis.col_b().y, trans.basis.col_b().z,
-                trans.basis.col_c().x, trans.basis.col_c().y, trans.basis.col_c().z,
-                trans.origin.x, trans.origin.y, trans.origin.z
+            return constructor(format!(
+                "Transform3D({}, {}, {}; {}, {}, {}; {}, {}, {}; {}, {}, {})",
+                _format_float(trans.basis.col_a().x, "f", options), _format_float(trans.basis.col_a().y, "f", options), _format_float(trans.basis.col_a().z, "f", options),
+                _format_float(trans.basis.col_b().x, "f", options), _format_float(trans.basis.col_b().y, "f", options), _format_float(trans.basis.col_b().z, "f", options),
+                _format_float(trans.basis.col_c().x, "f", options), _format_float(trans.basis.col_c().y, "f", options), _format_float(trans.basis.col_c().z, "f", options),
+                _format_float(trans.origin.x, "f", options), _format_float(trans.origin.y, "f", options), _format_float(trans.origin.z, "f", options)
+            ))
+        }
+
+        VariantType::Quaternion => {
+            let quat = Quaternion::from_variant(variant);
+            return constructor(format!(
+                "Quaternion({}, {}, {}, {})", _format_float(quat.x, "f", options), _format_float(quat.y, "f", options), _format_float(quat.z, "f", options), _format_float(quat.w, "f", options)
+            ));
+        }
+        VariantType::Basis => {
+            let basis = Basis::from_variant(variant);
+            return constructor(format!(
+                "Basis({}, {}, {}; {}, {}, {}; {}, {}, {})",
+                _format_float(basis.col_a().x, "f", options), _format_float(basis.col_a().y, "f", options), _format_float(basis.col_a().z, "f", options),
+                _format_float(basis.col_b().x, "f", options), _format_float(basis.col_b().y, "f", options), _format_float(basis.col_b().z, "f", options),
+                _format_float(basis.col_c().x, "f", options), _format_float(basis.col_c().y, "f", options), _format_float(basis.col_c().z, "f", options)
+            ))
+        }
+        VariantType::Plane => {
+            let plane = Plane::from_variant(variant);
+            return constructor(format!(
+                "Plane({}, {}, {}; {})", _format_float(plane.normal.x, "f", options), _format_float(plane.normal.y, "f", options), _format_float(plane.normal.z, "f", options), _format_float(plane.d, "f", options)
+            ));
+        }
+        VariantType::Projection => {
+            let proj = Projection::from_variant(variant);
+            return constructor(format!(
+                "Projection({}, {}, {}, {}; {}, {}, {}, {}; {}, {}, {}, {}; {}, {}, {}, {})",
+                _format_float(proj.cols[0].x, "f", options), _format_float(proj.cols[0].y, "f", options), _format_float(proj.cols[0].z, "f", options), _format_float(proj.cols[0].w, "f", options),
+                _format_float(proj.cols[1].x, "f", options), _format_float(proj.cols[1].y, "f", options), _format_float(proj.cols[1].z, "f", options), _format_float(proj.cols[1].w, "f", options),
+                _format_float(proj.cols[2].x, "f", options), _format_float(proj.cols[2].y, "f", options), _format_float(proj.cols[2].z, "f", options), _format_float(proj.cols[2].w, "f", options),
+                _format_float(proj.cols[3].x, "f", options), _format_float(proj.cols[3].y, "f", options), _format_float(proj.cols[3].z, "f", options), _format_float(proj.cols[3].w, "f", options)
             ))
         }
 
         VariantType::Array => {
-            let array = Array::<Variant>::from_variant(variant);
-            let mut string = String::from("[");
+            let anchor = match _anchor(variant, refs) {
+                Ok(anchor) => anchor,
+                Err(reference) => return reference
+            };
+            let array = array_elements(variant);
+            let mut string = anchor + "[";
             string += end_statement;
 
             let mut first = true;
@@ -157,9 +527,12 @@ fn _stringify(variant: &Variant, indent: &str, curr_indent: usize) -> GString {
                 }
                 
                 string += &_make_indent(indent, curr_indent + 1);
-                string += &_stringify(&v, indent, curr_indent + 1).to_string();
+                string += &_stringify(&v, options, curr_indent + 1, refs).to_string();
             }
 
+            if !array.is_empty() {
+                string += trailing_comma;
+            }
             string += end_statement;
             string += &_make_indent(indent, curr_indent);
             string += "]";
@@ -167,16 +540,20 @@ fn _stringify(variant: &Variant, indent: &str, curr_indent: usize) -> GString {
         }
 
         VariantType::Dictionary => {
+            let anchor = match _anchor(variant, refs) {
+                Ok(anchor) => anchor,
+                Err(reference) => return reference
+            };
             let dict = Dictionary::from_variant(variant);
             if dict.is_empty() {
-                return GString::from("{}");
+                return GString::from(anchor + "{}");
             }
-            let mut string = String::from("{");
+            let mut string = anchor + "{";
             string += end_statement;
 
             let mut first_key = true;
 
-            for key in dict.keys_shared() {
+            for key in _keys(&dict, options) {
                 if first_key {
                     first_key = false;
                 } else {
@@ -185,32 +562,396 @@ fn _stringify(variant: &Variant, indent: &str, curr_indent: usize) -> GString {
                 }
 
                 string += &_make_indent(indent, curr_indent + 1);
-                if key.get_type() == VariantType::String || key.get_type() == VariantType::StringName {
-                    let key = key.to_string();
-                    if _is_valid_identifier(&key) {
-                        string += &key;
-                    } else {
-                        string += &format!("\"{}\"", key.to_string().escape_debug());
-                    }
-                } else {
-                    string += "key_type_non_string"
-                }
+                string += &_stringify_key(&key, options, refs);
                 
                 string += colon;
 
-                string += &_stringify(&dict.get(key).unwrap(), indent, curr_indent + 1).to_string();
+                let value = dict.get(key.clone()).unwrap();
+                string += &match _hex_value(&key, &value, options) {
+                    Some(hex) => hex,
+                    None => _stringify(&value, options, curr_indent + 1, refs).to_string()
+                };
             }
 
+            string += trailing_comma;
             string += end_statement;
             string += &_make_indent(indent, curr_indent);
             string += "}";
             return string.into_godot();
         }
 
-        VariantType::Float => return GString::from(format!("{}f", variant)),
+        VariantType::Object => {
+            // Objects are written as their class name followed by their properties
+            return match variant.try_to::<Gd<Object>>() {
+                Ok(object) => {
+                    let anchor = match _anchor(variant, refs) {
+                        Ok(anchor) => anchor,
+                        Err(reference) => return reference
+                    };
+                    let properties = crate::co_object::storage_properties(&object);
+                    GString::from(format!(
+                        "{}{} {}", anchor, crate::co_object::class_name(&object),
+                        _stringify(&Variant::from(properties), options, curr_indent, refs)
+                    ))
+                },
+                Err(_) => GString::from("null")
+            };
+        }
+
+        VariantType::StringName => return GString::from(format!("&{}", _quote_string(&variant.to_string(), options.quote))),
+        VariantType::NodePath => return GString::from(format!("^{}", _quote_string(&variant.to_string(), options.quote))),
+
+        VariantType::Float => return GString::from(_format_float(f64::from_variant(variant), "f", options)),
+        VariantType::Int if options.hex_ints => return GString::from(format_hex(i64::from_variant(variant))),
         VariantType::Bool | VariantType::Int => return variant.stringify(),
-        _ => return GString::from(format!("\"{}\"", variant.to_string().escape_debug()))
+        _ => return GString::from(_quote_string(&variant.to_string(), options.quote))
+    }
+}
+
+
+/// Writes the shortest text that parses back to the exact same float, followed by its type suffix.
+/// Infinities and NaN are written as inf, -inf and nan, which are always floats.
+/// Writes an integer as a hexadecimal number, e.g. 0xFF or -0x10
+pub(crate) fn format_hex(value: i64) -> String {
+    if value < 0 {
+        format!("-0x{:X}", value.unsigned_abs())
+    } else {
+        format!("0x{:X}", value)
+    }
+}
+
+
+/// Tells whether the integer values of the key are written in hexadecimal, see StringifyOptions::hex_keys
+pub(crate) fn is_hex_key(key: &Variant, options: &StringifyOptions) -> bool {
+    !options.hex_keys.is_empty() && options.hex_keys.contains(&key.to_string())
+}
+
+
+/// Returns the value of the key written in hexadecimal if it is an integer and the options ask for it
+fn _hex_value(key: &Variant, value: &Variant, options: &StringifyOptions) -> Option<String> {
+    if value.get_type() != VariantType::Int || !is_hex_key(key, options) {
+        return None;
+    }
+    Some(format_hex(i64::from_variant(value)))
+}
+
+
+fn _format_float<T: std::fmt::Debug + std::fmt::Display>(value: T, suffix: &str, options: &StringifyOptions) -> String {
+    let string = match options.float_precision {
+        Some(precision) => {
+            let string = format!("{:.*}", precision, value);
+            if string.contains('.') {
+                String::from(string.trim_end_matches('0').trim_end_matches('.'))
+            } else {
+                string
+            }
+        },
+        None => format!("{:?}", value)
+    };
+
+    match string.as_str() {
+        "inf" | "-inf" => string,
+        "NaN" => String::from("nan"),
+        // Canonical text does not tell -0 from 0, which are equal
+        "-0.0" if options.canonical => format!("0{}", suffix),
+        // The sign of -0 is kept, it parses back to -0.0
+        _ if options.type_suffixes => match string.strip_suffix(".0") {
+            Some(integer) => format!("{}{}", integer, suffix),
+            None => string + suffix
+        },
+        // Without suffix, the decimal point tells floats from integers
+        _ if !string.contains(['.', 'e', 'E']) => string + ".0",
+        _ => string
+    }
+}
+
+
+fn _format_int(value: i32, options: &StringifyOptions) -> String {
+    if options.type_suffixes {
+        format!("{}i", value)
+    } else {
+        value.to_string()
+    }
+}
+
+
+/// Orders dictionary keys, numbers by value and anything else by type then text
+fn _compare_keys(a: &Variant, b: &Variant) -> Ordering {
+    match (a.get_type(), b.get_type()) {
+        (VariantType::Int, VariantType::Int) => i64::from_variant(a).cmp(&i64::from_variant(b)),
+        (VariantType::Float, VariantType::Float) => f64::from_variant(a).total_cmp(&f64::from_variant(b)),
+        (a_type, b_type) if a_type != b_type => (a_type as i32).cmp(&(b_type as i32)),
+        _ => a.to_string().cmp(&b.to_string())
+    }
+}
+
+
+/// Returns the keys of the dictionary in the order they are written
+fn _keys(dict: &Dictionary, options: &StringifyOptions) -> Vec<Variant> {
+    let mut keys: Vec<Variant> = dict.keys_shared().collect();
+    if options.canonical {
+        // The text of a key does not depend on the insertion order, unlike their hash or object ids
+        keys.sort_by_cached_key(|key| _stringify_key(key, options, &mut References::with_options(key, options)));
+    } else if options.sort_keys {
+        keys.sort_by(_compare_keys);
+    }
+    keys
+}
+
+
+/// Returns the type marker and the elements of packed arrays and typed arrays, None for any other value
+fn _packed_array(variant: &Variant, options: &StringifyOptions, refs: &mut References) -> Option<(&'static str, Vec<String>)> {
+    macro_rules! elements {
+        ($array_type:ident, $marker:literal, |$element:ident| $format:expr) => {{
+            // Elements are written without going through a Variant
+            let array = $array_type::from_variant(variant);
+            Some(($marker, (0..array.len()).map(|i| {
+                let $element = array.get(i);
+                $format
+            }).collect()))
+        }};
+        ($array_type:ident, $marker:literal) => {{
+            let array = $array_type::from_variant(variant);
+            Some(($marker, (0..array.len()).map(|i| {
+                _stringify(&Variant::from(array.get(i)), options, 0, refs).to_string()
+            }).collect()))
+        }};
+        ($array_type:ident, $marker:literal, compact) => {{
+            let array = $array_type::from_variant(variant);
+            Some(($marker, (0..array.len()).map(|i| {
+                // The constructor name is given by the array type
+                let element = _stringify(&Variant::from(array.get(i)), options, 0, refs).to_string();
+                String::from(&element[element.find('(').unwrap_or(0)..])
+            }).collect()))
+        }};
+    }
+
+    match variant.get_type() {
+        VariantType::PackedFloat32Array => elements!(PackedFloat32Array, "f32", |value| _format_float(value, "", options)),
+        VariantType::PackedFloat64Array => elements!(PackedFloat64Array, "f64"),
+        VariantType::PackedInt32Array => elements!(PackedInt32Array, "i32"),
+        VariantType::PackedInt64Array => elements!(PackedInt64Array, "i64"),
+        VariantType::PackedByteArray if options.hex_bytes => elements!(PackedByteArray, "B", |value| format!("0x{:02X}", value)),
+        VariantType::PackedByteArray => elements!(PackedByteArray, "B"),
+        VariantType::PackedStringArray => elements!(PackedStringArray, "String"),
+        VariantType::PackedVector2Array => elements!(PackedVector2Array, "Vector2", compact),
+        VariantType::PackedVector3Array => elements!(PackedVector3Array, "Vector3", compact),
+        VariantType::PackedVector4Array => elements!(PackedVector4Array, "Vector4", compact),
+        VariantType::PackedColorArray => elements!(PackedColorArray, "Color", compact),
+        VariantType::Array if StringNameArray::try_from_variant(variant).is_ok() => elements!(StringNameArray, "StringName"),
+        VariantType::Array if NodePathArray::try_from_variant(variant).is_ok() => elements!(NodePathArray, "NodePath"),
+        _ => None
+    }
+}
+
+
+/// Builds the layout of the variant for the pretty printer, following the same rules as _stringify
+fn _to_doc(variant: &Variant, options: &StringifyOptions, depth: usize, refs: &mut References) -> Doc {
+    if depth > crate::MAX_RECURSION_DEPTH {
+        godot_error!("[CerealObject] stringify max recursion depth reached");
+        return co_pretty::text("\"Max recursion depth reached\"");
+    }
+
+    if let Some((marker, elements)) = _packed_array(variant, options, refs) {
+        return _packed_doc(marker, elements, options);
+    }
+
+    match variant.get_type() {
+        VariantType::Array => {
+            let anchor = match _anchor(variant, refs) {
+                Ok(anchor) => anchor,
+                Err(reference) => return co_pretty::text(reference.to_string())
+            };
+            let items = array_elements(variant)
+                .iter_shared()
+                .map(|v| _to_doc(&v, options, depth + 1, refs))
+                .collect();
+            _collection_doc(anchor + "[", items, "]", options)
+        },
+        VariantType::Dictionary => {
+            let anchor = match _anchor(variant, refs) {
+                Ok(anchor) => anchor,
+                Err(reference) => return co_pretty::text(reference.to_string())
+            };
+            let dict = Dictionary::from_variant(variant);
+            let entries = _keys(&dict, options)
+                .into_iter()
+                .map(|key| {
+                    let value = dict.get(key.clone()).unwrap();
+                    Doc::Concat(vec![
+                        co_pretty::text(_stringify_key(&key, options, refs) + ": "),
+                        match _hex_value(&key, &value, options) {
+                            Some(hex) => co_pretty::text(hex),
+                            None => _to_doc(&value, options, depth + 1, refs)
+                        }
+                    ])
+                })
+                .collect();
+            _collection_doc(anchor + "{", entries, "}", options)
+        },
+        VariantType::Object => match variant.try_to::<Gd<Object>>() {
+            Ok(object) => {
+                let anchor = match _anchor(variant, refs) {
+                    Ok(anchor) => anchor,
+                    Err(reference) => return co_pretty::text(reference.to_string())
+                };
+                let properties = crate::co_object::storage_properties(&object);
+                Doc::Concat(vec![
+                    co_pretty::text(format!("{}{} ", anchor, crate::co_object::class_name(&object))),
+                    _to_doc(&Variant::from(properties), options, depth, refs)
+                ])
+            },
+            Err(_) => co_pretty::text("null")
+        },
+        _ => {
+            let string = _stringify(variant, options, depth, refs).to_string();
+            if string.ends_with(')') {
+                _constructor_doc(&string)
+            } else {
+                co_pretty::text(string)
+            }
+        }
+    }
+}
+
+
+fn _collection_doc(open: String, items: Vec<Doc>, close: &str, options: &StringifyOptions) -> Doc {
+    if items.is_empty() {
+        return co_pretty::text(open + close);
+    }
+
+    let mut contents = vec![Doc::SoftLine];
+    for (i, item) in items.into_iter().enumerate() {
+        if i != 0 {
+            contents.push(co_pretty::text(","));
+            contents.push(Doc::Line);
+        }
+        contents.push(item);
+    }
+    contents.push(_trailing_comma_doc(options));
+
+    co_pretty::group(Doc::Concat(vec![
+        co_pretty::text(open),
+        co_pretty::nest(Doc::Concat(contents)),
+        Doc::SoftLine,
+        co_pretty::text(close)
+    ]))
+}
+
+
+/// Packs the elements on as few lines as possible. Elements other than strings are aligned in columns.
+fn _packed_doc(marker: &str, elements: Vec<String>, options: &StringifyOptions) -> Doc {
+    if elements.is_empty() {
+        return co_pretty::text(format!("[{}]", marker));
+    }
+
+    let align = !matches!(marker, "String" | "StringName" | "NodePath");
+    let width = elements.iter().map(|element| element.chars().count()).max().unwrap_or(0);
+    let last = elements.len() - 1;
+    let items = elements.into_iter().enumerate().map(|(i, element)| {
+        let element = if align {
+            co_pretty::if_break(co_pretty::text(format!("{:>1$}", element, width)), co_pretty::text(element))
+        } else {
+            co_pretty::text(element)
+        };
+        if i == last { element } else { Doc::Concat(vec![element, co_pretty::text(",")]) }
+    }).collect();
+
+    co_pretty::group(Doc::Concat(vec![
+        co_pretty::text(format!("[{},", marker)),
+        co_pretty::nest(Doc::Concat(vec![Doc::Line, Doc::Fill(items), _trailing_comma_doc(options)])),
+        Doc::SoftLine,
+        co_pretty::text("]")
+    ]))
+}
+
+
+/// Writes constructors such as Transform2D(1f, 0f; 0f, 1f; 0f, 0f) with one row per line
+/// when they do not fit, aligning their columns
+fn _constructor_doc(string: &str) -> Doc {
+    let Some(open) = string.find('(') else { return co_pretty::text(string) };
+    let rows: Vec<Vec<&str>> = string[open + 1..string.len() - 1]
+        .split("; ")
+        .map(|row| row.split(", ").collect())
+        .collect();
+
+    let mut widths = Vec::<usize>::new();
+    for row in &rows {
+        widths.resize(widths.len().max(row.len()), 0);
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.chars().count());
+        }
+    }
+
+    let mut contents = vec![Doc::SoftLine];
+    for (r, row) in rows.iter().enumerate() {
+        if r != 0 {
+            contents.push(co_pretty::text(";"));
+            contents.push(Doc::Line);
+        }
+        for (i, cell) in row.iter().enumerate() {
+            if i != 0 {
+                contents.push(co_pretty::text(", "));
+            }
+            contents.push(co_pretty::if_break(co_pretty::text(format!("{:>1$}", cell, widths[i])), co_pretty::text(*cell)));
+        }
+    }
+
+    co_pretty::group(Doc::Concat(vec![
+        co_pretty::text(&string[..=open]),
+        co_pretty::nest(Doc::Concat(contents)),
+        Doc::SoftLine,
+        co_pretty::text(")")
+    ]))
+}
+
+
+fn _trailing_comma_doc(options: &StringifyOptions) -> Doc {
+    if options.trailing_commas {
+        co_pretty::if_break(co_pretty::text(","), co_pretty::text(""))
+    } else {
+        co_pretty::text("")
+    }
+}
+
+
+fn _stringify_key(key: &Variant, options: &StringifyOptions, refs: &mut References) -> String {
+    if key.get_type() == VariantType::String {
+        let key = key.to_string();
+        if is_valid_identifier(&key) {
+            key
+        } else {
+            _quote_string(&key, options.quote)
+        }
+    } else {
+        // Any other value is written as is, on a single line
+        let options = StringifyOptions { indent: String::new(), ..options.clone() };
+        _stringify(key, &options, 0, refs).to_string()
+    }
+}
+
+
+/// Puts the string between quotes, escaping the characters the parser would not read back as is
+fn _quote_string(string: &str, quote: char) -> String {
+    let mut quoted = String::with_capacity(string.len() + 2);
+    quoted.push(quote);
+    for c in string.chars() {
+        match c {
+            c if c == quote => {
+                quoted.push('\\');
+                quoted.push(c);
+            },
+            '\\' => quoted += "\\\\",
+            '\n' => quoted += "\\n",
+            '\t' => quoted += "\\t",
+            '\r' => quoted += "\\r",
+            '\0' => quoted += "\\0",
+            c if c.is_control() => quoted += &format!("\\u{:04x}", c as u32),
+            c => quoted.push(c)
+        }
     }
+    quoted.push(quote);
+    quoted
 }
 
 
@@ -219,9 +960,14 @@ fn _make_indent(indent: &str, indent_size: usize) -> String {
 }
 
 
-fn _is_valid_identifier(string: &String) -> bool {
+/// Returns true if the string can be written as a bare identifier, e.g. a dictionary key or a class name
+pub(crate) fn is_valid_identifier(string: &str) -> bool {
+    if crate::co_parser::is_keyword(string) {
+        return false;
+    }
+
     let string = string.as_bytes();
-    if !string[0].is_ascii_alphabetic() {
+    if string.is_empty() || !string[0].is_ascii_alphabetic() {
         return false;
     }
 
@@ -232,4 +978,57 @@ fn _is_valid_identifier(string: &String) -> bool {
     }
 
     return true;
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn shortest_exact_floats() {
+        let options = StringifyOptions::default();
+        assert_eq!(_format_float(0.1f64, "f", &options), "0.1f");
+        assert_eq!(_format_float(0.1f64 + 0.2f64, "f", &options), "0.30000000000000004f");
+        assert_eq!(_format_float(0.1f32, "f32", &options), "0.1f32");
+        assert_eq!(_format_float(1.0f64, "f", &options), "1f");
+
+        for value in [0.1f64 + 0.2f64, 1.0 / 3.0, f64::MAX, f64::MIN_POSITIVE, 1e-300] {
+            let string = _format_float(value, "", &StringifyOptions { type_suffixes: false, ..StringifyOptions::default() });
+            assert_eq!(string.parse::<f64>().unwrap().to_bits(), value.to_bits());
+        }
+
+        let options = StringifyOptions { float_precision: Some(3), type_suffixes: false, ..StringifyOptions::default() };
+        assert_eq!(_format_float(2.5f64, "f", &options), "2.5");
+        assert_eq!(_format_float(2.0f64, "f", &options), "2.0");
+    }
+
+
+    #[test]
+    fn special_floats() {
+        let options = StringifyOptions::default();
+        assert_eq!(_format_float(f64::INFINITY, "f", &options), "inf");
+        assert_eq!(_format_float(f64::NEG_INFINITY, "f", &options), "-inf");
+        assert_eq!(_format_float(f64::NAN, "f", &options), "nan");
+        assert_eq!(_format_float(f32::NAN, "f32", &options), "nan");
+    }
+
+
+    #[test]
+    fn hex_integers() {
+        assert_eq!(format_hex(255), "0xFF");
+        assert_eq!(format_hex(0), "0x0");
+        assert_eq!(format_hex(-16), "-0x10");
+        assert_eq!(format_hex(i64::MIN), "-0x8000000000000000");
+    }
+
+
+    #[test]
+    fn canonical_negative_zero() {
+        let canonical = StringifyOptions::canonical();
+        assert_eq!(_format_float(-0.0f64, "f", &canonical), "0f");
+        assert_eq!(_format_float(-0.0f32, "", &canonical), "0");
+        assert_eq!(_format_float(-0.5f64, "f", &canonical), "-0.5f");
+        assert_eq!(_format_float(-0.0f64, "f", &StringifyOptions::default()), "-0f");
+    }
+}
@@ -0,0 +1,302 @@
+use godot::prelude::*;
+
+use crate::co_create;
+use crate::co_parser::{self, ParseError, ParseErrorCode, Position, Span, TokenKind};
+
+
+/// Lossless view of a CE string.
+///
+/// The document keeps the original source and only records where each value is located in it,
+/// so that a value can be replaced without touching comments, whitespaces, key order or number
+/// spellings anywhere else in the file.
+pub struct Document {
+    source: String,
+    root: Node,
+    /// Objects can be created, see co_parser::parse_ex
+    allow_objects: bool,
+}
+
+
+struct Node {
+    /// Span of the value, without its anchor if any
+    span: Span,
+    kind: NodeKind,
+    /// The value, parsed along with the whole document so that references and anchors resolve
+    value: Variant,
+    anchor: Option<String>,
+}
+
+
+enum NodeKind {
+    /// Any value that is not navigated into (numbers, strings, constructors, typed arrays...)
+    Value,
+    Dictionary(Vec<Entry>),
+    Array(Vec<Node>),
+    /// A reference to an anchored value (*name), paths go on into the anchored value
+    Reference(String),
+}
+
+
+struct Entry {
+    key: Variant,
+    value: Node,
+}
+
+
+impl Document {
+    pub fn parse(source: String, allow_objects: bool) -> Result<Document, ParseError> {
+        let mut pos = Position::START;
+        let mut state = co_parser::ParseState::new(allow_objects);
+        let root = match _build(source.as_bytes(), &mut pos, 0, &mut state) {
+            Ok(root) => root,
+            Err(err) => {
+                state.free_objects();
+                return Err(err);
+            }
+        };
+        Ok(Document { source, root, allow_objects })
+    }
+
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+
+    /// Returns the value at the given path, None if there is none
+    pub fn get(&self, path: &str) -> Option<Variant> {
+        _find(&self.root, &self.root, &_split_path(path)).map(|node| node.value.clone())
+    }
+
+
+    pub fn has(&self, path: &str) -> bool {
+        _find(&self.root, &self.root, &_split_path(path)).is_some()
+    }
+
+
+    /// Replaces the value at the given path, or adds it if its parent is a dictionary that
+    /// does not have the key yet. Returns false if the path could not be reached.
+    pub fn set(&mut self, path: &str, value: &Variant) -> bool {
+        let segments = _split_path(path);
+
+        let (offset, end_offset, text) = match _find(&self.root, &self.root, &segments) {
+            Some(node) => {
+                let text = self._stringify_at(value, node.span.start.offset);
+                (node.span.start.offset, node.span.end.offset, text)
+            },
+            None => {
+                let Some((key, parent_path)) = segments.split_last() else { return false };
+                let Some(parent) = _find(&self.root, &self.root, parent_path) else { return false };
+                let NodeKind::Dictionary(entries) = &parent.kind else { return false };
+
+                let key = co_create::stringify_key(&Variant::from(*key)).to_string();
+                match entries.last() {
+                    None => {
+                        // Right after the opening curly bracket
+                        let offset = parent.span.start.offset + 1;
+                        let text = format!("{}: {}", key, self._stringify_at(value, parent.span.start.offset));
+                        (offset, offset, text)
+                    },
+                    Some(last) => {
+                        let offset = last.value.span.end.offset;
+                        let separator = if last.value.span.start.line == parent.span.start.line {
+                            String::from(", ")
+                        } else {
+                            format!(",\n{}", self._line_indent(last.value.span.start.offset))
+                        };
+                        let text = format!("{}{}: {}", separator, key, self._stringify_at(value, last.value.span.start.offset));
+                        (offset, offset, text)
+                    }
+                }
+            }
+        };
+
+        let mut source = self.source.clone();
+        source.replace_range(offset..end_offset, &text);
+        match Document::parse(source, self.allow_objects) {
+            Ok(document) => {
+                *self = document;
+                true
+            },
+            Err(_) => false
+        }
+    }
+
+
+    /// Stringifies the value so that it fits at the given offset
+    fn _stringify_at(&self, value: &Variant, offset: usize) -> String {
+        let line_indent = self._line_indent(offset);
+        let indent = if line_indent.starts_with('\t') { "\t" } else { "    " };
+        co_create::stringify_with_indent(value, indent)
+            .to_string()
+            .replace('\n', &format!("\n{}", line_indent))
+    }
+
+
+    /// Returns the whitespaces at the start of the line containing the offset
+    fn _line_indent(&self, offset: usize) -> String {
+        let line_start = match self.source[..offset].rfind('\n') {
+            Some(position) => position + 1,
+            None => 0
+        };
+        self.source[line_start..offset]
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .collect()
+    }
+}
+
+
+fn _split_path(path: &str) -> Vec<&str> {
+    path.split('.').filter(|segment| !segment.is_empty()).collect()
+}
+
+
+fn _find<'a>(root: &'a Node, node: &'a Node, path: &[&str]) -> Option<&'a Node> {
+    let Some((segment, rest)) = path.split_first() else { return Some(node) };
+
+    match &node.kind {
+        // Later keys override earlier ones when parsing, do the same here
+        NodeKind::Dictionary(entries) => _find(root, &entries.iter().rev().find(|entry| entry.key.to_string() == *segment)?.value, rest),
+        NodeKind::Array(items) => _find(root, items.get(segment.parse::<usize>().ok()?)?, rest),
+        NodeKind::Reference(name) => _find(root, _find_anchor(root, name, node.span.start.offset)?, path),
+        NodeKind::Value => None
+    }
+}
+
+
+/// Returns the node a reference at the offset points to: the last one declared with the anchor before it
+fn _find_anchor<'a>(node: &'a Node, name: &str, before: usize) -> Option<&'a Node> {
+    if node.span.start.offset >= before {
+        return None;
+    }
+
+    // Anchors inside a container come after the anchor of the container itself
+    let in_children = match &node.kind {
+        NodeKind::Dictionary(entries) => entries.iter().rev().find_map(|entry| _find_anchor(&entry.value, name, before)),
+        NodeKind::Array(items) => items.iter().rev().find_map(|item| _find_anchor(item, name, before)),
+        NodeKind::Reference(_) | NodeKind::Value => None
+    };
+    in_children.or(if node.anchor.as_deref() == Some(name) { Some(node) } else { None })
+}
+
+
+fn _build(string: &[u8], pos: &mut Position, depth: usize, state: &mut co_parser::ParseState) -> Result<Node, ParseError> {
+    if depth > crate::MAX_RECURSION_DEPTH {
+        return Err(ParseError::new(ParseErrorCode::MaxDepth, *pos, String::from("Reached max recursion depth")));
+    }
+
+    // Anchors are left out of the span, so that setting the value keeps its anchor
+    let value_start = *pos;
+    let (mut token, mut la_pos) = co_parser::lookahead_token(string, *pos);
+    let mut anchor = None;
+    if let TokenKind::Anchor(name) = token.kind {
+        anchor = Some(name);
+        (token, la_pos) = co_parser::lookahead_token(string, la_pos);
+    }
+    let start = token.span.start;
+    let node_anchor = anchor.clone();
+
+    let (kind, value) = match token.kind {
+        TokenKind::OpenCurlyBracket => {
+            *pos = la_pos;
+            let mut dictionary = Dictionary::new();
+            let mut entries = Vec::<Entry>::new();
+
+            if let Some(name) = anchor {
+                state.anchors.insert(name, Variant::from(dictionary.clone()));
+            }
+
+            while _next_item(string, pos, &entries, |kind| matches!(kind, TokenKind::CloseCurlyBracket))? {
+                let key = co_parser::parse_key(string, pos, depth + 1, state)?;
+
+                let token = co_parser::get_token(string, pos);
+                if !matches!(token.kind, TokenKind::Colon) {
+                    return Err(_unexpected_token(token.kind, token.span.start, "colon"));
+                }
+
+                let value = _build(string, pos, depth + 1, state)?;
+                dictionary.insert(key.clone(), value.value.clone());
+                entries.push(Entry { key, value });
+            }
+
+            (NodeKind::Dictionary(entries), Variant::from(dictionary))
+        },
+        TokenKind::OpenBracket if !_is_typed_array(string, la_pos) => {
+            *pos = la_pos;
+            let mut array = Array::<Variant>::new();
+            let mut items = Vec::<Node>::new();
+
+            if let Some(name) = anchor {
+                state.anchors.insert(name, Variant::from(array.clone()));
+            }
+
+            while _next_item(string, pos, &items, |kind| matches!(kind, TokenKind::CloseBracket))? {
+                let item = _build(string, pos, depth + 1, state)?;
+                array.push(item.value.clone());
+                items.push(item);
+            }
+
+            (NodeKind::Array(items), Variant::from(array))
+        },
+        TokenKind::Reference(name) => {
+            *pos = value_start;
+            (NodeKind::Reference(name), co_parser::parse_value(string, pos, depth, state)?)
+        },
+        _ => {
+            // The parser registers the anchor of other values itself
+            *pos = value_start;
+            (NodeKind::Value, co_parser::parse_value(string, pos, depth, state)?)
+        }
+    };
+
+    Ok(Node { span: Span { start, end: *pos }, kind, value, anchor: node_anchor })
+}
+
+
+/// Moves after the separator of the next item of a collection.
+/// Returns false and moves after the closing token if the collection is over.
+fn _next_item<T>(string: &[u8], pos: &mut Position, items: &[T], is_closing: fn(&TokenKind) -> bool) -> Result<bool, ParseError> {
+    let (la_token, la_pos) = co_parser::lookahead_token(string, *pos);
+
+    if is_closing(&la_token.kind) {
+        *pos = la_pos;
+        return Ok(false);
+    }
+
+    if let TokenKind::Comma = la_token.kind {
+        if items.is_empty() {
+            return Err(ParseError::new(ParseErrorCode::UnexpectedToken, la_token.span.start, String::from("Unexpected comma")));
+        }
+        *pos = la_pos;
+
+        // Trailing comma
+        let (la_token, la_pos) = co_parser::lookahead_token(string, *pos);
+        if is_closing(&la_token.kind) {
+            *pos = la_pos;
+            return Ok(false);
+        }
+    } else if !items.is_empty() {
+        return Err(_unexpected_token(la_token.kind, la_token.span.start, "comma"));
+    }
+
+    Ok(true)
+}
+
+
+fn _is_typed_array(string: &[u8], pos: Position) -> bool {
+    match co_parser::lookahead_token(string, pos).0.kind {
+        TokenKind::Identifier(ident) => co_parser::is_array_marker(&ident),
+        _ => false
+    }
+}
+
+
+fn _unexpected_token(kind: TokenKind, pos: Position, expected: &str) -> ParseError {
+    let code = match kind {
+        TokenKind::Eof => ParseErrorCode::UnexpectedEof,
+        TokenKind::Error(code, _) => code,
+        _ => ParseErrorCode::UnexpectedToken
+    };
+    ParseError::new(code, pos, format!("Expected {}, found: {:?}", expected, kind))
+}
@@ -0,0 +1,79 @@
+use godot::prelude::*;
+use godot::engine::{DirAccess, FileAccess};
+use godot::engine::file_access::ModeFlags;
+use godot::engine::global::Error;
+
+use crate::co_create::{self, StringifyOptions};
+use crate::co_parser::{self, ParseError, ParseErrorCode, Position};
+
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+
+pub enum LoadError {
+    /// The file could not be opened or read
+    File(Error),
+    Parse(ParseError),
+}
+
+
+/// Reads and parses the CE file at the path, which may be a res:// or user:// path.
+/// Objects are only created if allowed, see co_parser::parse_ex.
+pub fn load(path: &str, allow_objects: bool) -> Result<Variant, LoadError> {
+    let text = read_text(path)?;
+    co_parser::parse_ex(&text, allow_objects).map_err(LoadError::Parse)
+}
+
+
+/// Reads the text of the file, without its UTF-8 BOM and with \n line endings
+pub fn read_text(path: &str) -> Result<String, LoadError> {
+    let Some(mut file) = FileAccess::open(GString::from(path), ModeFlags::READ) else {
+        return Err(LoadError::File(FileAccess::get_open_error()));
+    };
+    let bytes = file.get_buffer(file.get_length() as i64).to_vec();
+    match file.get_error() {
+        Error::OK | Error::ERR_FILE_EOF => {},
+        err => return Err(LoadError::File(err))
+    }
+
+    let bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(&bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Ok(text.replace("\r\n", "\n")),
+        Err(err) => {
+            // Report the position of the first invalid byte
+            let valid = unsafe { std::str::from_utf8_unchecked(&bytes[..err.valid_up_to()]) };
+            let line_start = valid.rfind('\n').map_or(0, |index| index + 1);
+            let pos = Position {
+                offset: err.valid_up_to(),
+                line: valid.matches('\n').count() + 1,
+                column: valid[line_start..].chars().count() + 1
+            };
+            Err(LoadError::Parse(ParseError::new(ParseErrorCode::MalformedString, pos, String::from("Invalid UTF-8"))))
+        }
+    }
+}
+
+
+/// Writes the variant to the file at the path. The text is written to a temporary file first,
+/// which then replaces the file, so that the file is never left half written.
+pub fn save(path: &str, variant: &Variant, options: &StringifyOptions) -> Result<(), Error> {
+    let text = co_create::stringify_with_options(variant, options);
+    let tmp_path = format!("{}.tmp", path);
+
+    let Some(mut file) = FileAccess::open(GString::from(tmp_path.as_str()), ModeFlags::WRITE) else {
+        return Err(FileAccess::get_open_error());
+    };
+    file.store_string(text);
+    let err = file.get_error();
+    file.close();
+
+    let err = match err {
+        Error::OK => DirAccess::rename_absolute(GString::from(tmp_path.as_str()), GString::from(path)),
+        err => err
+    };
+    if err != Error::OK {
+        DirAccess::remove_absolute(GString::from(tmp_path.as_str()));
+        return Err(err);
+    }
+    Ok(())
+}
@@ -0,0 +1,177 @@
+use crate::co_parser::{self, ParseErrorCode, Position, Token, TokenKind};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Style {
+    Text,
+    /// Brackets, parenthesis, colons and commas
+    Symbol,
+    /// Dictionary keys and property names
+    Key,
+    String,
+    /// Numbers and their type suffix
+    Number,
+    /// true, false and null
+    Keyword,
+    /// Constructors and array type markers
+    Type,
+    /// Class of an object
+    ClassName,
+    /// Anchors and references
+    Anchor,
+    Comment,
+}
+
+
+/// Part of a line drawn with a style, from its column up to the next region
+pub struct Region {
+    /// Column in characters, starting at 0
+    pub column: usize,
+    pub style: Style,
+}
+
+
+/// Splits each line of the CE text into regions to highlight. The text does not need to be valid,
+/// what cannot be tokenized is drawn as plain text.
+pub fn highlight(text: &str) -> Vec<Vec<Region>> {
+    let string = text.as_bytes();
+    let mut lines: Vec<Vec<Region>> = text.split('\n').map(|_| Vec::new()).collect();
+
+    let mut tokens = Vec::<Token>::new();
+    let mut pos = Position::START;
+    loop {
+        let gap_start = pos;
+        let mut token = co_parser::get_token(string, &mut pos);
+
+        // Comments are skipped by the tokenizer, they are what is left between tokens
+        for comment_start in _find_comments(string, gap_start, token.span.start) {
+            _push(&mut lines, comment_start, Style::Comment);
+        }
+
+        match token.kind {
+            TokenKind::Eof => break,
+            TokenKind::Error(ParseErrorCode::UnterminatedComment, _) => {
+                _push(&mut lines, token.span.start, Style::Comment);
+                break;
+            },
+            _ => {}
+        }
+        // Some errors do not move the position, skip the character that caused them
+        if pos.offset == token.span.start.offset {
+            co_parser::skip_char(string, &mut pos);
+            token.span.end = pos;
+        }
+        tokens.push(token);
+    }
+
+    for (i, token) in tokens.iter().enumerate() {
+        let previous = if i > 0 { Some(&tokens[i - 1].kind) } else { None };
+        let next = tokens.get(i + 1).map(|token| &token.kind);
+        _push(&mut lines, token.span.start, _style(&token.kind, previous, next));
+    }
+
+    // Lines start with the style of the end of the previous line, e.g. inside a multiline string or comment
+    let mut carried = Style::Text;
+    for regions in &mut lines {
+        regions.sort_by_key(|region| region.column);
+        if regions.first().map_or(true, |region| region.column > 0) {
+            regions.insert(0, Region { column: 0, style: carried });
+        }
+        carried = regions.last().unwrap().style;
+    }
+
+    lines
+}
+
+
+/// Returns the style of the token, which depends on the tokens around it
+fn _style(kind: &TokenKind, previous: Option<&TokenKind>, next: Option<&TokenKind>) -> Style {
+    match kind {
+        TokenKind::Identifier(_) | TokenKind::String(_) if matches!(next, Some(TokenKind::Colon)) => Style::Key,
+        TokenKind::String(_) | TokenKind::StringName(_) | TokenKind::NodePath(_) => Style::String,
+        TokenKind::Number(_) => Style::Number,
+        TokenKind::Identifier(ident) => {
+            if co_parser::is_keyword(ident) {
+                Style::Keyword
+            } else if co_parser::is_number_suffix(ident) && matches!(previous, Some(TokenKind::Number(_))) {
+                Style::Number
+            } else if co_parser::is_array_marker(ident) && matches!(previous, Some(TokenKind::OpenBracket)) {
+                Style::Type
+            } else if co_parser::is_constructor_name(ident) && matches!(next, Some(TokenKind::OpenParenthesis)) {
+                Style::Type
+            } else if matches!(next, Some(TokenKind::OpenCurlyBracket)) {
+                Style::ClassName
+            } else {
+                Style::Text
+            }
+        },
+        TokenKind::Anchor(_) | TokenKind::Reference(_) => Style::Anchor,
+        TokenKind::Error(ParseErrorCode::UnterminatedString | ParseErrorCode::MalformedString, _) => Style::String,
+        TokenKind::Error(..) | TokenKind::Eof => Style::Text,
+        _ => Style::Symbol
+    }
+}
+
+
+/// Returns the start of each comment between the positions, which only hold whitespace and comments
+fn _find_comments(string: &[u8], mut pos: Position, end: Position) -> Vec<Position> {
+    let mut comments = Vec::new();
+    while pos.offset < end.offset {
+        if string[pos.offset] <= 32 {
+            co_parser::skip_char(string, &mut pos);
+            continue;
+        }
+        comments.push(pos);
+        if !co_parser::skip_comment(string, &mut pos) {
+            break;
+        }
+    }
+    comments
+}
+
+
+fn _push(lines: &mut [Vec<Region>], pos: Position, style: Style) {
+    lines[pos.line - 1].push(Region { column: pos.column - 1, style });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn styles(lines: &[Vec<Region>], line: usize) -> Vec<(usize, Style)> {
+        lines[line].iter().map(|region| (region.column, region.style)).collect()
+    }
+
+
+    #[test]
+    fn tokens_and_comments() {
+        let lines = highlight("{ hp: 5i } // c\n/* a\nb */ \"s\"");
+        assert_eq!(lines.len(), 3);
+        assert_eq!(styles(&lines, 0), [
+            (0, Style::Symbol), (2, Style::Key), (4, Style::Symbol), (6, Style::Number),
+            (7, Style::Number), (9, Style::Symbol), (11, Style::Comment)
+        ]);
+        assert_eq!(styles(&lines, 1), [(0, Style::Comment)]);
+        // The comment goes on from the previous line
+        assert_eq!(styles(&lines, 2), [(0, Style::Comment), (5, Style::String)]);
+    }
+
+
+    #[test]
+    fn several_comments_between_tokens() {
+        let lines = highlight("1 /* x */ // y\n# a\n  # b\n2");
+        assert_eq!(styles(&lines, 0), [(0, Style::Number), (2, Style::Comment), (10, Style::Comment)]);
+        assert_eq!(styles(&lines, 1), [(0, Style::Comment)]);
+        assert_eq!(styles(&lines, 2), [(0, Style::Comment), (2, Style::Comment)]);
+        assert_eq!(styles(&lines, 3), [(0, Style::Number)]);
+    }
+
+
+    #[test]
+    fn invalid_text() {
+        let lines = highlight("[1, \"abc");
+        assert_eq!(styles(&lines, 0), [(0, Style::Symbol), (1, Style::Number), (2, Style::Symbol), (4, Style::String)]);
+    }
+}
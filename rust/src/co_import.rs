@@ -0,0 +1,109 @@
+use godot::prelude::*;
+use godot::engine::utilities;
+
+
+/// Checks that the value follows the schema:
+/// - a string is the name of the expected type ("int", "String", "Vector2", "Dictionary", a class name...), "Variant" allowing anything
+/// - a dictionary expects a dictionary or an object having all its keys, whose values follow the schema of the key
+/// - an array expects an array, whose elements all follow the schema of its first element if any
+///
+/// Returns the path of the first value that does not follow the schema and what was expected.
+pub fn validate(value: &Variant, schema: &Variant, path: &str) -> Result<(), String> {
+    let location = if path.is_empty() { "root" } else { path };
+
+    match schema.get_type() {
+        VariantType::String | VariantType::StringName => {
+            let expected = schema.to_string();
+            if expected == "Variant" || _type_name(value) == expected {
+                return Ok(());
+            }
+            // Objects also match their base classes
+            if let Ok(object) = value.try_to::<Gd<Object>>() {
+                if object.is_class(GString::from(expected.as_str())) {
+                    return Ok(());
+                }
+            }
+            Err(format!("{}: expected {}, found {}", location, expected, _type_name(value)))
+        },
+        VariantType::Dictionary => {
+            let fields = match value.get_type() {
+                VariantType::Dictionary => Dictionary::from_variant(value),
+                VariantType::Object => match value.try_to::<Gd<Object>>() {
+                    Ok(object) => crate::co_object::storage_properties(&object),
+                    Err(_) => return Err(format!("{}: expected Dictionary, found null", location))
+                },
+                _ => return Err(format!("{}: expected Dictionary, found {}", location, _type_name(value)))
+            };
+
+            for (key, key_schema) in Dictionary::from_variant(schema).iter_shared() {
+                let key_path = _join(path, &key.to_string());
+                match fields.get(key) {
+                    Some(field) => validate(&field, &key_schema, &key_path)?,
+                    None => return Err(format!("{}: missing", key_path))
+                }
+            }
+            Ok(())
+        },
+        VariantType::Array => {
+            if value.get_type() != VariantType::Array {
+                return Err(format!("{}: expected Array, found {}", location, _type_name(value)));
+            }
+            let array = crate::co_create::array_elements(value);
+
+            if let Some(element_schema) = crate::co_create::array_elements(schema).first() {
+                for (i, element) in array.iter_shared().enumerate() {
+                    validate(&element, &element_schema, &_join(path, &i.to_string()))?;
+                }
+            }
+            Ok(())
+        },
+        _ => Err(format!("{}: unsupported schema {}", location, schema))
+    }
+}
+
+
+/// Creates a resource of the given class from the parsed data, which is either an object of
+/// that class or a dictionary of its properties
+pub fn instantiate_resource(class_name: &str, data: &Variant) -> Result<Gd<Resource>, String> {
+    if let Ok(resource) = data.try_to::<Gd<Resource>>() {
+        if crate::co_object::class_name(&resource.clone().upcast()) == class_name {
+            return Ok(resource);
+        }
+    }
+
+    let Ok(properties) = data.try_to::<Dictionary>() else {
+        return Err(format!("Expected a {} or a Dictionary, found {}", class_name, _type_name(data)));
+    };
+    let Some(mut object) = crate::co_object::instantiate(class_name) else {
+        return Err(format!("Cannot instantiate class {}", class_name));
+    };
+
+    match Variant::from(object.clone()).try_to::<Gd<Resource>>() {
+        Ok(resource) => {
+            crate::co_object::set_properties(&mut object, &properties)?;
+            Ok(resource)
+        },
+        Err(_) => {
+            crate::co_object::free(object);
+            Err(format!("{} is not a Resource", class_name))
+        }
+    }
+}
+
+
+/// Returns the name of the type of the value as written in schemas
+fn _type_name(value: &Variant) -> String {
+    match value.try_to::<Gd<Object>>() {
+        Ok(object) => crate::co_object::class_name(&object),
+        Err(_) => utilities::type_string(value.get_type() as i64).to_string()
+    }
+}
+
+
+fn _join(path: &str, segment: &str) -> String {
+    if path.is_empty() {
+        String::from(segment)
+    } else {
+        format!("{}.{}", path, segment)
+    }
+}
@@ -0,0 +1,116 @@
+use godot::prelude::*;
+use godot::engine::{ClassDb, ProjectSettings, ResourceLoader, Script};
+
+
+/// PROPERTY_USAGE_STORAGE, set on the properties that are saved with the object
+const PROPERTY_USAGE_STORAGE: i64 = 2;
+
+
+/// Returns the name of the script class of the object if it has one, its engine class otherwise
+pub fn class_name(object: &Gd<Object>) -> String {
+    match _script(object).and_then(|script| _global_class(&script.get_path().to_string())) {
+        Some(name) => name,
+        None => object.get_class().to_string()
+    }
+}
+
+
+/// Returns the properties of the object that are saved along with it, in declaration order.
+/// A script without class_name is given first, by its path.
+pub fn storage_properties(object: &Gd<Object>) -> Dictionary {
+    let mut properties = Dictionary::new();
+
+    if let Some(script) = _script(object) {
+        let path = script.get_path().to_string();
+        if _global_class(&path).is_none() {
+            // Built-in scripts cannot be loaded on their own
+            if path.is_empty() || path.contains("::") {
+                godot_error!("[CerealObject] The script of {} is not saved in its own file, the object is written without it", object.get_class());
+            } else {
+                properties.insert("script", path);
+            }
+        }
+    }
+
+    for property in object.get_property_list().iter_shared() {
+        let usage = property.get("usage").map_or(0, |usage| i64::from_variant(&usage));
+        let name = property.get("name").map_or(String::new(), |name| name.to_string());
+
+        // The script is given by the class name or added above
+        if usage & PROPERTY_USAGE_STORAGE == 0 || name.is_empty() || name == "script" {
+            continue;
+        }
+
+        let value = object.get(StringName::from(name.as_str()));
+        properties.insert(name, value);
+    }
+
+    properties
+}
+
+
+/// Creates an object from either a global script class name, or an engine class name
+pub fn instantiate(class_name: &str) -> Option<Gd<Object>> {
+    for class in ProjectSettings::singleton().get_global_class_list().iter_shared() {
+        if !class.get("class").is_some_and(|name| name.to_string() == class_name) {
+            continue;
+        }
+
+        let path = class.get("path")?.to_string();
+        let mut script = ResourceLoader::singleton().load(GString::from(path))?;
+        return script.call(StringName::from("new"), &[]).try_to::<Gd<Object>>().ok();
+    }
+
+    let class_name = StringName::from(class_name);
+    let class_db = ClassDb::singleton();
+    if class_db.class_exists(class_name.clone()) && class_db.can_instantiate(class_name.clone()) {
+        return class_db.instantiate(class_name).try_to::<Gd<Object>>().ok();
+    }
+
+    None
+}
+
+
+/// Frees an object that ends up unused, e.g. when parsing its properties failed.
+/// RefCounted objects free themselves once no longer referenced.
+pub fn free(object: Gd<Object>) {
+    if !object.is_class(GString::from("RefCounted")) {
+        object.free();
+    }
+}
+
+
+/// Sets the properties on the object. A script path is loaded and set first, so that
+/// the properties declared by the script exist when they are set.
+pub fn set_properties(object: &mut Gd<Object>, properties: &Dictionary) -> Result<(), String> {
+    if let Some(path) = properties.get("script").filter(|path| path.get_type() == VariantType::String) {
+        let Some(script) = ResourceLoader::singleton().load(GString::from_variant(&path)) else {
+            return Err(format!("Cannot load the script {} of {}", path, object.get_class()));
+        };
+        object.set_script(script.to_variant());
+    }
+
+    for (name, value) in properties.iter_shared() {
+        let name = name.to_string();
+        if name != "script" || value.get_type() != VariantType::String {
+            object.set(StringName::from(name.as_str()), value);
+        }
+    }
+    Ok(())
+}
+
+
+fn _script(object: &Gd<Object>) -> Option<Gd<Script>> {
+    object.get_script().try_to::<Gd<Script>>().ok()
+}
+
+
+/// Returns the global class name (class_name) of the script at the path, if it has one
+fn _global_class(script_path: &str) -> Option<String> {
+    for class in ProjectSettings::singleton().get_global_class_list().iter_shared() {
+        if class.get("path").is_some_and(|path| path.to_string() == script_path) {
+            return class.get("class").map(|name| name.to_string());
+        }
+    }
+    None
+}
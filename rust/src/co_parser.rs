@@ -1,8 +1,43 @@
+use std::collections::HashMap;
+
 use godot::prelude::*;
+use godot::builtin::real;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A position in a CE string
+pub struct Position {
+    /// Byte offset from the start of the string
+    pub offset: usize,
+    /// Line, starting at 1
+    pub line: usize,
+    /// Column in characters, starting at 1
+    pub column: usize,
+}
+
+
+impl Position {
+    pub const START: Position = Position { offset: 0, line: 1, column: 1 };
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Part of a CE string going from start (included) to end (excluded)
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+
+#[derive(Debug)]
+pub(crate) struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
 
 
 #[derive(Debug)]
-enum Token {
+pub(crate) enum TokenKind {
     OpenCurlyBracket,
     CloseCurlyBracket,
     OpenBracket,
@@ -13,16 +48,20 @@ enum Token {
     Identifier(String),
     String(String),
     Number(String),
+    StringName(String),
+    NodePath(String),
+    Anchor(String),
+    Reference(String),
 
     Colon,
     Comma,
 
     Eof,
-    Error(String)
+    Error(ParseErrorCode, String)
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum MarkerType {
     Variant,
     
@@ -33,6 +72,8 @@ enum MarkerType {
     Byte,
     
     String,
+    StringName,
+    NodePath,
 
     Color,
     Vector2,
@@ -48,295 +89,324 @@ enum MarkerType {
 
     Transform2D,
     Transform3D,
+
+    Quaternion,
+    Basis,
+    Plane,
+    Projection,
 }
 
 
 
-pub fn parse(string: String) -> Variant {
-    let string = string.as_bytes();
-    let mut index = 0usize;
-    let mut line = 1usize;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Kind of error that made a parse fail
+pub enum ParseErrorCode {
+    Ok = 0,
+    UnexpectedEof,
+    UnexpectedCharacter,
+    UnexpectedToken,
+    UnknownIdentifier,
+    UnknownClass,
+    UnknownReference,
+    UnterminatedString,
+    UnterminatedComment,
+    MalformedString,
+    MalformedNumber,
+    TypeMismatch,
+    UnsupportedType,
+    MaxDepth,
+    /// The data does not start with the header of binary CE
+    InvalidHeader,
+    /// The file could not be opened or read
+    FileError,
+    /// The data holds an object, and objects were not allowed
+    ObjectNotAllowed,
+}
+
+
+#[derive(Debug, Clone)]
+/// Error returned when parsing a CE string fails
+pub struct ParseError {
+    pub code: ParseErrorCode,
+    pub message: String,
+    /// Byte offset of the error
+    pub offset: usize,
+    /// Line of the error, starting at 1
+    pub line: usize,
+    /// Column of the error, starting at 1
+    pub column: usize,
+}
+
+
+impl ParseError {
+    pub fn new(code: ParseErrorCode, pos: Position, message: String) -> Self {
+        ParseError { code, message, offset: pos.offset, line: pos.line, column: pos.column }
+    }
+}
+
 
-    return match _parse(string, &mut index, &mut line, 0) {
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Error line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+
+
+pub fn parse(string: String, allow_objects: bool) -> Variant {
+    return match parse_ex(&string, allow_objects) {
         Ok(var) => var,
         Err(e) => {
             godot_error!("[CerealObject] {}", e);
             Variant::nil()
         }
-    }; // TODO: better error management
+    };
+}
+
+
+/// Parses the string, returning a detailed error on failure.
+/// Objects are only created if allowed, see CerealObject::parse_string.
+pub fn parse_ex(string: &str, allow_objects: bool) -> Result<Variant, ParseError> {
+    let string = string.as_bytes();
+    let mut pos = Position::START;
+    let mut state = ParseState::new(allow_objects);
+
+    let result = _parse(string, &mut pos, 0, &mut state, None);
+    if result.is_err() {
+        state.free_objects();
+    }
+    return result;
+}
+
+
+type StringNameArray = Array<StringName>;
+type NodePathArray = Array<NodePath>;
+
+
+/// What a parse keeps from one value to the next
+pub(crate) struct ParseState {
+    /// Values declared with an anchor (&name), that can be referenced later on (*name).
+    /// Containers and objects are added before their content, so that it can reference them.
+    pub anchors: HashMap<String, Variant>,
+    /// Objects can be created, see parse_ex
+    pub allow_objects: bool,
+    /// Objects created so far, freed if the parse fails
+    pub objects: Vec<Gd<Object>>,
+}
+
+
+impl ParseState {
+    pub fn new(allow_objects: bool) -> Self {
+        ParseState { anchors: HashMap::new(), allow_objects, objects: Vec::new() }
+    }
+
+
+    /// Frees the objects created so far, once the parse failed
+    pub fn free_objects(&mut self) {
+        for object in self.objects.drain(..) {
+            crate::co_object::free(object);
+        }
+    }
+}
+
+
+/// Parses the value starting at pos, and moves pos right after it
+pub(crate) fn parse_value(string: &[u8], pos: &mut Position, depth: usize, state: &mut ParseState) -> Result<Variant, ParseError> {
+    _parse(string, pos, depth, state, None)
+}
+
+
+/// Parses a dictionary key. Keys can be any value, bare identifiers being string keys.
+pub(crate) fn parse_key(string: &[u8], pos: &mut Position, depth: usize, state: &mut ParseState) -> Result<Variant, ParseError> {
+    let (la_token, la_pos) = lookahead_token(string, *pos);
+    if let TokenKind::Identifier(ident) = la_token.kind {
+        // Not to be mistaken with constructors, e.g. Vector2i(1i, 2i): "tile"
+        if !is_keyword(&ident) && matches!(lookahead_token(string, la_pos).0.kind, TokenKind::Colon) {
+            *pos = la_pos;
+            return Ok(Variant::from(ident));
+        }
+    }
+
+    _parse(string, pos, depth, state, None)
+}
+
+
+/// Returns true if the identifier stands for a value on its own
+pub(crate) fn is_keyword(ident: &str) -> bool {
+    matches!(ident, "true" | "false" | "null") || _is_special_number(ident)
+}
+
+
+/// Infinity and NaN are lexed as numbers
+fn _is_special_number(ident: &str) -> bool {
+    matches!(ident, "inf" | "nan")
+}
+
+
+/// Returns true if the identifier gives the type of an array when placed right after its opening bracket
+pub(crate) fn is_array_marker(ident: &str) -> bool {
+    !matches!(get_marker_type(ident), MarkerType::Variant)
+}
+
+
+/// Returns true if the identifier is the name of a type written with a constructor, e.g. Vector2
+pub(crate) fn is_constructor_name(ident: &str) -> bool {
+    _is_constructor(&get_marker_type(ident))
+}
+
+
+/// Returns true if the identifier can follow a number to give its type, e.g. f32
+pub(crate) fn is_number_suffix(ident: &str) -> bool {
+    _is_number_marker(&get_marker_type(ident))
 }
 
 
 macro_rules! error {
-    ($line:expr) => (
-        Err(format!("Error line {}", $line))
+    ($code:expr, $pos:expr) => (
+        error!($code, $pos, "{:?}", $code)
     );
-    ($line:expr, $($x:tt),+) => (
-        Err(format!("Error line {}: {}", $line, format!($($x),+)))
+    ($code:expr, $pos:expr, $($x:tt),+) => (
+        Err(ParseError::new($code, $pos, format!($($x),+)))
     );
 }
 
 
-macro_rules! expect_token_error {
-    ($expected_token:pat_param, $token:expr, $line:expr) => {
-        error!($line, "Expected token {:?} got {:?}", (stringify!($expected_token)), $token)
-    };
+fn _token_error_code(kind: &TokenKind) -> ParseErrorCode {
+    match kind {
+        TokenKind::Eof => ParseErrorCode::UnexpectedEof,
+        TokenKind::Error(code, _) => *code,
+        _ => ParseErrorCode::UnexpectedToken
+    }
 }
 
 
-macro_rules! expect_token {
-    ($expected_token:pat_param, $val:expr, $line:expr) => {
-        match $val {
-            $expected_token => Ok(()),
-            token => expect_token_error!($expected_token, token, $line)
-        }
+macro_rules! expect_token_error {
+    ($expected_token:pat_param, $kind:expr, $span:expr) => {
+        error!(_token_error_code(&$kind), $span.start, "Expected token {:?} got {:?}", (stringify!($expected_token)), $kind)
     };
 }
 
 
-macro_rules! extract_token {
-    ($expected_token:pat_param => $ret:expr, $val:expr, $line:expr) => {
-        match $val {
-            $expected_token => Ok($ret),
-            token => expect_token_error!($expected_token, token, $line)
+macro_rules! expect_token {
+    ($expected_token:pat_param, $val:expr) => {{
+        let token: Token = $val;
+        match token.kind {
+            $expected_token => Ok(()),
+            kind => expect_token_error!($expected_token, kind, token.span)
         }
-    };
+    }};
 }
 
 
-fn _parse(string: &[u8], index: &mut usize, line: &mut usize, depth: usize) -> Result<Variant, String> {
+/// Parses the next value. If an anchor is given, the value is registered under it.
+fn _parse(string: &[u8], pos: &mut Position, depth: usize, state: &mut ParseState, mut anchor: Option<String>) -> Result<Variant, ParseError> {
     if depth > crate::MAX_RECURSION_DEPTH {
-        return error!(*line, "Reached max recursion depth");
+        return error!(ParseErrorCode::MaxDepth, *pos, "Reached max recursion depth");
     }
 
-    match get_token(string, index, line) {
-        Token::OpenCurlyBracket => return _parse_dict(string, index, line, depth + 1),
-        Token::OpenBracket => return _parse_array(string, index, line, depth + 1),
-        Token::String(s) => return Ok(Variant::from(s)),
-        Token::Number(nb) => _get_number(nb, string, index, line),
-        Token::Identifier(ident) => {
+    let token = get_token(string, pos);
+    let variant = match token.kind {
+        TokenKind::OpenCurlyBracket => return _parse_dict(string, pos, depth + 1, state, anchor),
+        TokenKind::OpenBracket => return _parse_array(string, pos, depth + 1, state, anchor),
+        TokenKind::Anchor(name) => {
+            if anchor.is_some() {
+                return error!(ParseErrorCode::UnexpectedToken, token.span.start, "A value cannot have two anchors: &{}", name);
+            }
+            return _parse(string, pos, depth, state, Some(name));
+        },
+        TokenKind::Reference(name) => match state.anchors.get(&name) {
+            Some(variant) => Ok(variant.clone()),
+            None => error!(ParseErrorCode::UnknownReference, token.span.start, "Unknown reference *{}", name)
+        },
+        TokenKind::String(s) => Ok(Variant::from(s)),
+        TokenKind::StringName(s) => Ok(Variant::from(StringName::from(s.as_str()))),
+        TokenKind::NodePath(s) => Ok(Variant::from(NodePath::from(s.as_str()))),
+        TokenKind::Number(nb) => _get_number(nb, token.span, string, pos, &MarkerType::Variant),
+        TokenKind::Identifier(ident) => {
             match ident.as_str() {
+                // Keep in sync with is_keyword
                 "true"             => Ok(Variant::from(true)),
                 "false"            => Ok(Variant::from(false)),
                 "null"             => Ok(Variant::nil()),
                 _ => match get_marker_type(&ident) {
-                    MarkerType::Color => {
-                        expect_token!(Token::OpenParenthesis, get_token(string, index, line), *line)?;
-                        let r = u8::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let g = u8::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let b = u8::from_variant(&_parse_number(string, index, line)?);
-                        
-                        // Either get the alpha or a closing parenthesis
-                        let a = match get_token(string, index, line) {
-                            Token::Comma => {
-                                let a = u8::from_variant(&_parse_number(string, index, line)?);
-                                expect_token!(Token::CloseParenthesis, get_token(string, index, line), *line)?;
-                                a
-                            },
-                            Token::CloseParenthesis => 255u8,                   
-                            token => error!(line, "Expected Comma or CloseParenthesis token, got: {:?}", token)?
-                        };
-                        Ok(Variant::from(Color::from_rgba8(r, g, b, a)))
-                    },
-                    MarkerType::Vector2 => {
-                        expect_token!(Token::OpenParenthesis, get_token(string, index, line), *line)?;
-                        let x = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let y = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::CloseParenthesis, get_token(string, index, line), *line)?;
-                        Ok(Variant::from(Vector2::new(x, y)))
-                    },
-                    MarkerType::Vector3 => {
-                        expect_token!(Token::OpenParenthesis, get_token(string, index, line), *line)?;
-                        let x = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let y = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let z = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::CloseParenthesis, get_token(string, index, line), *line)?;
-                        Ok(Variant::from(Vector3::new(x, y, z)))
-                    },
-                    MarkerType::Vector4 => {
-                        expect_token!(Token::OpenParenthesis, get_token(string, index, line), *line)?;
-                        let x = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let y = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let z = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let w = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::CloseParenthesis, get_token(string, index, line), *line)?;
-                        Ok(Variant::from(Vector4::new(x, y, z, w)))
-                    },
-                    MarkerType::Vector2i => {
-                        expect_token!(Token::OpenParenthesis, get_token(string, index, line), *line)?;
-                        let x = i32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let y = i32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::CloseParenthesis, get_token(string, index, line), *line)?;
-                        Ok(Variant::from(Vector2i::new(x, y)))
-                    },
-                    MarkerType::Vector3i => {
-                        expect_token!(Token::OpenParenthesis, get_token(string, index, line), *line)?;
-                        let x = i32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let y = i32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let z = i32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::CloseParenthesis, get_token(string, index, line), *line)?;
-                        Ok(Variant::from(Vector3i::new(x, y, z)))
-                    },
-                    MarkerType::Vector4i => {
-                        expect_token!(Token::OpenParenthesis, get_token(string, index, line), *line)?;
-                        let x = i32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let y = i32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let z = i32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let w = i32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::CloseParenthesis, get_token(string, index, line), *line)?;
-                        Ok(Variant::from(Vector4i::new(x, y, z, w)))
-                    },
-                    MarkerType::Rect2 => {
-                        expect_token!(Token::OpenParenthesis, get_token(string, index, line), *line)?;
-                        let x = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let y = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-
-                        let w = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let h = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::CloseParenthesis, get_token(string, index, line), *line)?;
-                        Ok(Variant::from(Rect2::new(Vector2::new(x, y), Vector2::new(w, h))))
-                    },
-                    MarkerType::Rect2i => {
-                        expect_token!(Token::OpenParenthesis, get_token(string, index, line), *line)?;
-                        let x = i32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let y = i32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-
-                        let w = i32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let h = i32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::CloseParenthesis, get_token(string, index, line), *line)?;
-                        Ok(Variant::from(Rect2i::new(Vector2i::new(x, y), Vector2i::new(w, h))))
-                    },
-                    MarkerType::Aabb => {
-                        expect_token!(Token::OpenParenthesis, get_token(string, index, line), *line)?;
-                        let x = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let y = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let z = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-
-                        let w = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let h = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let l = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::CloseParenthesis, get_token(string, index, line), *line)?;
-                        Ok(Variant::from(Aabb::new(Vector3::new(x, y, z), Vector3::new(w, h, l))))
-                    },
-                    MarkerType::Transform2D => {
-                        expect_token!(Token::OpenParenthesis, get_token(string, index, line), *line)?;
-                        let x0 = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let x1 = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-
-                        let y0 = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let y1 = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-
-                        let ox = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let oy = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::CloseParenthesis, get_token(string, index, line), *line)?;
-                        Ok(Variant::from(Transform2D::from_cols(Vector2::new(x0, x1), Vector2::new(y0, y1), Vector2::new(ox, oy))))
-                    },
-                    MarkerType::Transform3D => {
-                        expect_token!(Token::OpenParenthesis, get_token(string, index, line), *line)?;
-                        let x0 = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let x1 = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let x2 = f32::from_variant(&_parse_number(string, index, line)?);
-
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let y0 = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let y1 = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let y2 = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-
-                        let z0 = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let z1 = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let z2 = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-
-                        let ox = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let oy = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::Comma, get_token(string, index, line), *line)?;
-                        let oz = f32::from_variant(&_parse_number(string, index, line)?);
-                        expect_token!(Token::CloseParenthesis, get_token(string, index, line), *line)?;
-                        Ok(Variant::from(Transform3D::from_cols(
-                            Vector3::new(x0, x1, x2),
-                            Vector3::new(y0, y1, y2),
-                            Vector3::new(z0, z1, z2),
-                            Vector3::new(ox, oy, oz)
-                        )))
-                    },
-                    _ => error!(*line, "Unexpected identifier {}", ident)
+                    marker if _is_constructor(&marker) => _parse_constructor(&marker, string, pos),
+                    _ => {
+                        // An identifier followed by a curly bracket is an object
+                        let (la_token, la_pos) = lookahead_token(string, *pos);
+                        if let TokenKind::OpenCurlyBracket = la_token.kind {
+                            if !state.allow_objects {
+                                return error!(ParseErrorCode::ObjectNotAllowed, token.span.start, "Objects are not allowed: {}", ident);
+                            }
+                            *pos = la_pos;
+                            match crate::co_object::instantiate(&ident) {
+                                Some(mut object) => {
+                                    state.objects.push(object.clone());
+                                    if let Some(name) = anchor.take() {
+                                        state.anchors.insert(name, Variant::from(object.clone()));
+                                    }
+                                    let properties = _parse_dict(string, pos, depth + 1, state, None)?;
+                                    match crate::co_object::set_properties(&mut object, &Dictionary::from_variant(&properties)) {
+                                        Ok(()) => Ok(Variant::from(object)),
+                                        Err(err) => error!(ParseErrorCode::UnknownClass, token.span.start, "{}", err)
+                                    }
+                                },
+                                None => error!(ParseErrorCode::UnknownClass, token.span.start, "Cannot instantiate class {}", ident)
+                            }
+                        } else {
+                            error!(ParseErrorCode::UnknownIdentifier, token.span.start, "Unexpected identifier {}", ident)
+                        }
+                    }
                 }
             }
         },
-        Token::Error(err) => {
-            return error!(*line, "{}", err);
+        TokenKind::Error(code, err) => {
+            return error!(code, token.span.start, "{}", err);
         },
-        t => {
-            return error!(*line, "Unexpected token: {:?}", t);
+        kind => {
+            return error!(_token_error_code(&kind), token.span.start, "Unexpected token: {:?}", kind);
         }
+    }?;
+
+    if let Some(name) = anchor {
+        state.anchors.insert(name, variant.clone());
     }
+    Ok(variant)
 }
 
 
 macro_rules! _parse_collection {
     (Variant; $closing_token:ident) => {
-        |string: &[u8], index: &mut usize, line: &mut usize, depth: usize| {
+        |string: &[u8], pos: &mut Position, depth: usize, state: &mut ParseState, anchor: Option<String>| {
         let mut collection = Array::<Variant>::new();
         let mut first = true;
 
+        if let Some(name) = anchor {
+            state.anchors.insert(name, Variant::from(collection.clone()));
+        }
+
         loop {
             // Look for the ending token or comma
-            let (la_token, la_index, la_line) = lookahead_token(string, *index, *line);
+            let (la_token, la_pos) = lookahead_token(string, *pos);
 
-            if let Token::$closing_token = la_token {
-                *index = la_index;
-                *line = la_line;
+            if let TokenKind::$closing_token = la_token.kind {
+                *pos = la_pos;
                 break Ok(Variant::from(collection));
             }
 
-            if let Token::Comma = la_token {
-                if first { return error!(la_line, "Unexpected comma"); }
-                *index = la_index;
-                *line = la_line;
+            if let TokenKind::Comma = la_token.kind {
+                if first { return error!(ParseErrorCode::UnexpectedToken, la_token.span.start, "Unexpected comma"); }
+                *pos = la_pos;
+
+                // Trailing comma
+                if let TokenKind::$closing_token = lookahead_token(string, *pos).0.kind {
+                    continue;
+                }
             } else {
-                if !first { return error!(la_line, "Expected comma, found: {:?}", la_token); }
+                if !first { return error!(_token_error_code(&la_token.kind), la_token.span.start, "Expected comma, found: {:?}", la_token.kind); }
             }
 
             // Add variant to array
-            let variant = _parse(string, index, line, depth)?;
+            let variant = _parse(string, pos, depth, state, None)?;
             collection.push(variant);
 
             // No longer the first pass
@@ -344,36 +414,40 @@ macro_rules! _parse_collection {
         }
     }};
     ($array_type:ident, $godot_type:ident, $rust_type:ident; $closing_token:ident) => {
-        |string: &[u8], index: &mut usize, line: &mut usize, depth: usize| {
+        |string: &[u8], pos: &mut Position, depth: usize, state: &mut ParseState, marker: &MarkerType| {
         let mut collection = $array_type::new();
         let mut first = true;
 
         loop {
             // Look for the ending token or comma
-            let (la_token, la_index, la_line) = lookahead_token(string, *index, *line);
+            let (la_token, la_pos) = lookahead_token(string, *pos);
 
-            if let Token::$closing_token = la_token {
-                *index = la_index;
-                *line = la_line;
+            if let TokenKind::$closing_token = la_token.kind {
+                *pos = la_pos;
                 break Ok(Variant::from(collection));
             }
 
-            if let Token::Comma = la_token {
-                if first { return error!(la_line, "Unexpected comma"); }
-                *index = la_index;
-                *line = la_line;
+            if let TokenKind::Comma = la_token.kind {
+                if first { return error!(ParseErrorCode::UnexpectedToken, la_token.span.start, "Unexpected comma"); }
+                *pos = la_pos;
+
+                // Trailing comma
+                if let TokenKind::$closing_token = lookahead_token(string, *pos).0.kind {
+                    continue;
+                }
             } else {
-                if !first { return error!(la_line, "Expected comma, found: {:?}", la_token); }
+                if !first { return error!(_token_error_code(&la_token.kind), la_token.span.start, "Expected comma, found: {:?}", la_token.kind); }
             }
 
             // Add variant to array
-            let variant = _parse(string, index, line, depth)?;
-                
-                
+            _skip_ignored(string, pos);
+            let start = *pos;
+            let variant = _parse_element(string, pos, depth, state, marker)?;
+
             if variant.get_type() == VariantType::$godot_type {
                 collection.push($rust_type::from_variant(&variant));
             } else {
-                return error!(*line, "Expected {}, found {}", (stringify!($godot_type)), variant);
+                return error!(ParseErrorCode::TypeMismatch, start, "Expected {}, found {}", (stringify!($godot_type)), variant);
             }
 
             // No longer the first pass
@@ -383,82 +457,417 @@ macro_rules! _parse_collection {
 }
 
 
-fn _parse_number(string: &[u8], index: &mut usize, line: &mut usize) -> Result<Variant, String> {
-    extract_token!(
-        Token::Number(nb) => _get_number(nb, string, index, line)?,
-        get_token(string, index, line), *line
+/// Parses an element of a typed array, pos being at its start
+fn _parse_element(string: &[u8], pos: &mut Position, depth: usize, state: &mut ParseState, marker: &MarkerType) -> Result<Variant, ParseError> {
+    if _is_char_at(string, pos.offset, b'(') {
+        // Compact syntax, the constructor name is given by the array type
+        _parse_constructor(marker, string, pos)
+    } else if _is_number_marker(marker) {
+        // Read numbers straight with the type of the array, to avoid rounding them twice
+        _parse_number(string, pos, marker)
+    } else {
+        _parse(string, pos, depth, state, None)
+    }
+}
+
+
+/// Parses the next element of an array of the given type (e.g. "i32"), and checks its type
+pub(crate) fn parse_array_element(string: &[u8], pos: &mut Position, depth: usize, state: &mut ParseState, array_type: &str) -> Result<Variant, ParseError> {
+    let marker = get_marker_type(array_type);
+    let Some(element_type) = _element_type(&marker) else {
+        return error!(ParseErrorCode::UnsupportedType, *pos, "Unsupported array type: {:?}", marker);
+    };
+
+    _skip_ignored(string, pos);
+    let start = *pos;
+    let variant = _parse_element(string, pos, depth, state, &marker)?;
+    if variant.get_type() != element_type {
+        return error!(ParseErrorCode::TypeMismatch, start, "Expected {:?}, found {}", element_type, variant);
+    }
+    Ok(variant)
+}
+
+
+/// Returns the type of the elements of typed arrays with the given marker, None if there are no such arrays
+pub(crate) fn array_element_type(array_type: &str) -> Option<VariantType> {
+    _element_type(&get_marker_type(array_type))
+}
+
+
+/// Checks that the value can be an element of an array of the given type (e.g. "B"),
+/// integers must fit in the elements as when they are parsed
+pub(crate) fn check_array_element(array_type: &str, variant: &Variant) -> Result<(), String> {
+    let marker = get_marker_type(array_type);
+    let Some(element_type) = _element_type(&marker) else {
+        return Err(format!("Unsupported array type: {}", array_type));
+    };
+    if variant.get_type() != element_type {
+        return Err(format!("Expected {:?}, found {}", element_type, variant));
+    }
+
+    let fits = match marker {
+        MarkerType::Byte => _fit_integer::<u8>(i128::from(i64::from_variant(variant))).map(|_| ()),
+        MarkerType::Int32 => _fit_integer::<i32>(i128::from(i64::from_variant(variant))).map(|_| ()),
+        _ => Ok(())
+    };
+    fits.map_err(|err| format!("{} does not fit in {} elements ({})", variant, array_type, err))
+}
+
+
+fn _element_type(marker: &MarkerType) -> Option<VariantType> {
+    // Keep in sync with _parse_array
+    match marker {
+        MarkerType::Float32 | MarkerType::Float64 => Some(VariantType::Float),
+        MarkerType::Int32 | MarkerType::Int64 | MarkerType::Byte => Some(VariantType::Int),
+        MarkerType::String => Some(VariantType::String),
+        MarkerType::StringName => Some(VariantType::StringName),
+        MarkerType::NodePath => Some(VariantType::NodePath),
+        MarkerType::Vector2 => Some(VariantType::Vector2),
+        MarkerType::Vector3 => Some(VariantType::Vector3),
+        MarkerType::Vector4 => Some(VariantType::Vector4),
+        MarkerType::Color => Some(VariantType::Color),
+        _ => None
+    }
+}
+
+
+/// Parses the parenthesis part of a constructor, e.g. (1f, 2f) for a Vector2
+fn _parse_constructor(marker: &MarkerType, string: &[u8], pos: &mut Position) -> Result<Variant, ParseError> {
+    match marker {
+        MarkerType::Color => {
+            expect_token!(TokenKind::OpenParenthesis, get_token(string, pos))?;
+            let mut channels = vec![_read_number(string, pos)?];
+            for _ in 0..3 {
+                let token = get_token(string, pos);
+                match token.kind {
+                    TokenKind::Comma => channels.push(_read_number(string, pos)?),
+                    TokenKind::CloseParenthesis if channels.len() == 3 => break, // The alpha is optional
+                    kind => error!(_token_error_code(&kind), token.span.start, "Expected Comma or CloseParenthesis token, got: {:?}", kind)?
+                }
+            }
+            if channels.len() == 4 {
+                expect_token!(TokenKind::CloseParenthesis, get_token(string, pos))?;
+            }
+
+            // Channels are either all bytes, or floats as soon as one of them is
+            if channels.iter().any(|(_, nb_part, marker)| _is_float_number(nb_part, marker)) {
+                let mut rgba = [1.0; 4];
+                for (i, (start, nb_part, _)) in channels.iter().enumerate() {
+                    rgba[i] = match nb_part.replace('_', "").parse::<f32>() {
+                        Ok(value) => value,
+                        Err(_) => match _parse_integer::<i64>(nb_part) {
+                            Ok(value) => value as f32,
+                            Err(err) => return error!(ParseErrorCode::MalformedNumber, *start, "Malformed color channel: {} ({})", nb_part, err)
+                        }
+                    };
+                }
+                Ok(Variant::from(Color::from_rgba(rgba[0], rgba[1], rgba[2], rgba[3])))
+            } else {
+                let mut rgba = [255; 4];
+                for (i, (start, nb_part, _)) in channels.iter().enumerate() {
+                    rgba[i] = match _parse_integer::<u8>(nb_part) {
+                        Ok(value) => value,
+                        Err(err) => return error!(ParseErrorCode::MalformedNumber, *start, "Color channels must be between 0 and 255: {} ({})", nb_part, err)
+                    };
+                }
+                Ok(Variant::from(Color::from_rgba8(rgba[0], rgba[1], rgba[2], rgba[3])))
+            }
+        },
+        MarkerType::Vector2 => {
+            let [x, y] = _parse_components::<2>(string, pos)?;
+            Ok(Variant::from(Vector2::new(x, y)))
+        },
+        MarkerType::Vector3 => {
+            let [x, y, z] = _parse_components::<3>(string, pos)?;
+            Ok(Variant::from(Vector3::new(x, y, z)))
+        },
+        MarkerType::Vector4 => {
+            let [x, y, z, w] = _parse_components::<4>(string, pos)?;
+            Ok(Variant::from(Vector4::new(x, y, z, w)))
+        },
+        MarkerType::Vector2i => {
+            expect_token!(TokenKind::OpenParenthesis, get_token(string, pos))?;
+            let x = _parse_i32(string, pos)?;
+            expect_token!(TokenKind::Comma, get_token(string, pos))?;
+            let y = _parse_i32(string, pos)?;
+            expect_token!(TokenKind::CloseParenthesis, get_token(string, pos))?;
+            Ok(Variant::from(Vector2i::new(x, y)))
+        },
+        MarkerType::Vector3i => {
+            expect_token!(TokenKind::OpenParenthesis, get_token(string, pos))?;
+            let x = _parse_i32(string, pos)?;
+            expect_token!(TokenKind::Comma, get_token(string, pos))?;
+            let y = _parse_i32(string, pos)?;
+            expect_token!(TokenKind::Comma, get_token(string, pos))?;
+            let z = _parse_i32(string, pos)?;
+            expect_token!(TokenKind::CloseParenthesis, get_token(string, pos))?;
+            Ok(Variant::from(Vector3i::new(x, y, z)))
+        },
+        MarkerType::Vector4i => {
+            expect_token!(TokenKind::OpenParenthesis, get_token(string, pos))?;
+            let x = _parse_i32(string, pos)?;
+            expect_token!(TokenKind::Comma, get_token(string, pos))?;
+            let y = _parse_i32(string, pos)?;
+            expect_token!(TokenKind::Comma, get_token(string, pos))?;
+            let z = _parse_i32(string, pos)?;
+            expect_token!(TokenKind::Comma, get_token(string, pos))?;
+            let w = _parse_i32(string, pos)?;
+            expect_token!(TokenKind::CloseParenthesis, get_token(string, pos))?;
+            Ok(Variant::from(Vector4i::new(x, y, z, w)))
+        },
+        MarkerType::Rect2 => {
+            let [x, y, w, h] = _parse_components::<4>(string, pos)?;
+            Ok(Variant::from(Rect2::new(Vector2::new(x, y), Vector2::new(w, h))))
+        },
+        MarkerType::Rect2i => {
+            expect_token!(TokenKind::OpenParenthesis, get_token(string, pos))?;
+            let x = _parse_i32(string, pos)?;
+            expect_token!(TokenKind::Comma, get_token(string, pos))?;
+            let y = _parse_i32(string, pos)?;
+            expect_token!(TokenKind::Comma, get_token(string, pos))?;
+
+            let w = _parse_i32(string, pos)?;
+            expect_token!(TokenKind::Comma, get_token(string, pos))?;
+            let h = _parse_i32(string, pos)?;
+            expect_token!(TokenKind::CloseParenthesis, get_token(string, pos))?;
+            Ok(Variant::from(Rect2i::new(Vector2i::new(x, y), Vector2i::new(w, h))))
+        },
+        MarkerType::Aabb => {
+            let [x, y, z, w, h, l] = _parse_components::<6>(string, pos)?;
+            Ok(Variant::from(Aabb::new(Vector3::new(x, y, z), Vector3::new(w, h, l))))
+        },
+        MarkerType::Transform2D => {
+            let [x0, x1, y0, y1, ox, oy] = _parse_components::<6>(string, pos)?;
+            Ok(Variant::from(Transform2D::from_cols(Vector2::new(x0, x1), Vector2::new(y0, y1), Vector2::new(ox, oy))))
+        },
+        MarkerType::Transform3D => {
+            let [x0, x1, x2, y0, y1, y2, z0, z1, z2, ox, oy, oz] = _parse_components::<12>(string, pos)?;
+            Ok(Variant::from(Transform3D::from_cols(
+                Vector3::new(x0, x1, x2),
+                Vector3::new(y0, y1, y2),
+                Vector3::new(z0, z1, z2),
+                Vector3::new(ox, oy, oz)
+            )))
+        },
+        MarkerType::Quaternion => {
+            let [x, y, z, w] = _parse_components::<4>(string, pos)?;
+            Ok(Variant::from(Quaternion::new(x, y, z, w)))
+        },
+        MarkerType::Basis => {
+            let [x0, x1, x2, y0, y1, y2, z0, z1, z2] = _parse_components::<9>(string, pos)?;
+            Ok(Variant::from(Basis::from_cols(
+                Vector3::new(x0, x1, x2),
+                Vector3::new(y0, y1, y2),
+                Vector3::new(z0, z1, z2)
+            )))
+        },
+        MarkerType::Plane => {
+            let [x, y, z, d] = _parse_components::<4>(string, pos)?;
+            // Plane::new requires a normalized normal, which may not be the case
+            Ok(Variant::from(Plane { normal: Vector3::new(x, y, z), d }))
+        },
+        MarkerType::Projection => {
+            let [
+                x0, x1, x2, x3,
+                y0, y1, y2, y3,
+                z0, z1, z2, z3,
+                w0, w1, w2, w3
+            ] = _parse_components::<16>(string, pos)?;
+            Ok(Variant::from(Projection::from_cols(
+                Vector4::new(x0, x1, x2, x3),
+                Vector4::new(y0, y1, y2, y3),
+                Vector4::new(z0, z1, z2, z3),
+                Vector4::new(w0, w1, w2, w3)
+            )))
+        },
+        _ => {
+            let token = get_token(string, pos);
+            error!(ParseErrorCode::UnexpectedToken, token.span.start, "{:?} values have no constructor", marker)
+        }
+    }
+}
+
+
+/// Reads a number and its type suffix without converting it, e.g. to check its range first.
+/// Returns its position, its text and its type marker, MarkerType::Variant if it has none.
+fn _read_number(string: &[u8], pos: &mut Position) -> Result<(Position, String, MarkerType), ParseError> {
+    let token = get_token(string, pos);
+    let nb_part = match token.kind {
+        TokenKind::Number(nb) => nb,
+        kind => return expect_token_error!(TokenKind::Number(_), kind, token.span)
+    };
+
+    let (la_token, la_pos) = lookahead_token(string, *pos);
+    if let TokenKind::Identifier(ident) = la_token.kind {
+        let marker = get_marker_type(&ident);
+        if _is_number_marker(&marker) {
+            *pos = la_pos;
+            return Ok((token.span.start, nb_part, marker));
+        }
+    }
+    Ok((token.span.start, nb_part, MarkerType::Variant))
+}
+
+
+/// Tells whether a number read by _read_number is a float
+fn _is_float_number(nb_part: &str, marker: &MarkerType) -> bool {
+    match marker {
+        MarkerType::Float32 | MarkerType::Float64 => true,
+        MarkerType::Variant => _is_float_literal(nb_part),
+        _ => false
+    }
+}
+
+
+/// Parses an integer component, e.g. of a Vector2i
+fn _parse_i32(string: &[u8], pos: &mut Position) -> Result<i32, ParseError> {
+    let (start, nb_part, marker) = _read_number(string, pos)?;
+    if _is_float_number(&nb_part, &marker) {
+        return error!(ParseErrorCode::TypeMismatch, start, "Expected Int32, found float {}", nb_part);
+    }
+
+    let value = match marker {
+        MarkerType::Byte => _parse_integer::<u8>(&nb_part).map(i32::from),
+        _ => _parse_integer::<i32>(&nb_part)
+    };
+    match value {
+        Ok(value) => Ok(value),
+        Err(err) => error!(ParseErrorCode::MalformedNumber, start, "Malformed Int32: {} ({})", nb_part, err)
+    }
+}
+
+
+fn _is_constructor(marker: &MarkerType) -> bool {
+    matches!(marker,
+        MarkerType::Color | MarkerType::Vector2 | MarkerType::Vector3 | MarkerType::Vector4
+        | MarkerType::Vector2i | MarkerType::Vector3i | MarkerType::Vector4i
+        | MarkerType::Rect2 | MarkerType::Rect2i | MarkerType::Aabb
+        | MarkerType::Transform2D | MarkerType::Transform3D
+        | MarkerType::Quaternion | MarkerType::Basis | MarkerType::Plane | MarkerType::Projection
     )
 }
 
 
-fn _get_number(nb_part: String, string: &[u8], index: &mut usize, line: &mut usize) -> Result<Variant, String> {
+/// Parses N real numbers separated by commas between parenthesis, e.g. (1f, 2f; 3f)
+fn _parse_components<const N: usize>(string: &[u8], pos: &mut Position) -> Result<[real; N], ParseError> {
+    let mut components = [0 as real; N];
+
+    expect_token!(TokenKind::OpenParenthesis, get_token(string, pos))?;
+    for (i, component) in components.iter_mut().enumerate() {
+        if i != 0 {
+            expect_token!(TokenKind::Comma, get_token(string, pos))?;
+        }
+        *component = _parse_real(string, pos)?;
+    }
+    expect_token!(TokenKind::CloseParenthesis, get_token(string, pos))?;
+
+    Ok(components)
+}
+
+
+/// Parses a number with the precision of the engine's real_t. It is read straight from the
+/// text so that it does not get rounded twice when real_t is a single precision float.
+fn _parse_real(string: &[u8], pos: &mut Position) -> Result<real, ParseError> {
+    let token = get_token(string, pos);
+    let nb_part = match token.kind {
+        TokenKind::Number(nb) => nb,
+        kind => return expect_token_error!(TokenKind::Number(_), kind, token.span)
+    };
+
+    // Skip the type suffix if any, the precision is given by real_t
+    let (la_token, la_pos) = lookahead_token(string, *pos);
+    if let TokenKind::Identifier(ident) = la_token.kind {
+        if _is_number_marker(&get_marker_type(&ident)) {
+            *pos = la_pos;
+        }
+    }
+
+    match nb_part.replace('_', "").parse::<real>() {
+        Ok(value) => Ok(value),
+        Err(err) => error!(ParseErrorCode::MalformedNumber, token.span.start, "Malformed real: {} ({})", nb_part, err)
+    }
+}
+
+
+/// Parses a number. Numbers without a type suffix are given the default type, unless it is MarkerType::Variant.
+fn _parse_number(string: &[u8], pos: &mut Position, default_marker: &MarkerType) -> Result<Variant, ParseError> {
+    let token = get_token(string, pos);
+    match token.kind {
+        TokenKind::Number(nb) => _get_number(nb, token.span, string, pos, default_marker),
+        kind => expect_token_error!(TokenKind::Number(_), kind, token.span)
+    }
+}
+
+
+fn _is_number_marker(marker: &MarkerType) -> bool {
+    matches!(marker, MarkerType::Byte | MarkerType::Float32 | MarkerType::Float64 | MarkerType::Int32 | MarkerType::Int64)
+}
+
+
+fn _get_number(nb_part: String, span: Span, string: &[u8], pos: &mut Position, default_marker: &MarkerType) -> Result<Variant, ParseError> {
     // Look for type marker afterwards
-    let (la_token, la_index, la_line) = lookahead_token(string, *index, *line);
+    let (la_token, la_pos) = lookahead_token(string, *pos);
 
-    let type_marker = match la_token {
-        Token::Identifier(ident) => get_marker_type(ident.as_str()),
-        _ => MarkerType::Variant
+    let type_marker = match la_token.kind {
+        TokenKind::Identifier(ident) => match get_marker_type(ident.as_str()) {
+            marker if _is_number_marker(&marker) => {
+                *pos = la_pos;
+                marker
+            },
+            _ => *default_marker
+        },
+        _ => *default_marker
     };
 
     match type_marker {
         MarkerType::Byte    => {
-            *index = la_index;
-            *line  = la_line;
-            match nb_part.parse::<u8>() {
+            match _parse_integer::<u8>(&nb_part) {
                 Ok(value) => return Ok(Variant::from(value)),
                 Err(err) =>
-                    return error!(*line, "Malformed Byte: {} ({})", nb_part, err)
+                    return error!(ParseErrorCode::MalformedNumber, span.start, "Malformed Byte: {} ({})", nb_part, err)
             }
         },
         MarkerType::Float32 => {
-            *index = la_index;
-            *line  = la_line;
-            match nb_part.parse::<f32>() {
+            match nb_part.replace('_', "").parse::<f32>() {
                 Ok(value) => return Ok(Variant::from(value)),
                 Err(err) =>
-                    return error!(*line, "Malformed Float32: {} ({})", nb_part, err)
+                    return error!(ParseErrorCode::MalformedNumber, span.start, "Malformed Float32: {} ({})", nb_part, err)
             }
         },
         MarkerType::Float64 => {
-            *index = la_index;
-            *line  = la_line;
-            match nb_part.parse::<f64>() {
+            match nb_part.replace('_', "").parse::<f64>() {
                 Ok(value) => return Ok(Variant::from(value)),
                 Err(err) =>
-                    return error!(*line, "Malformed Float64: {} ({})", nb_part, err)
+                    return error!(ParseErrorCode::MalformedNumber, span.start, "Malformed Float64: {} ({})", nb_part, err)
             }
         },
         MarkerType::Int32   => {
-            *index = la_index;
-            *line  = la_line;
-            match nb_part.parse::<i32>() {
+            match _parse_integer::<i32>(&nb_part) {
                 Ok(value) => return Ok(Variant::from(value)),
                 Err(err) =>
-                    return error!(*line, "Malformed Int32: {} ({})", nb_part, err)
+                    return error!(ParseErrorCode::MalformedNumber, span.start, "Malformed Int32: {} ({})", nb_part, err)
             }
         },
         MarkerType::Int64   => {
-            *index = la_index;
-            *line  = la_line;
-            match nb_part.parse::<i64>() {
+            match _parse_integer::<i64>(&nb_part) {
                 Ok(value) => return Ok(Variant::from(value)),
                 Err(err) =>
-                    return error!(*line, "Malformed Int64: {} ({})", nb_part, err)
+                    return error!(ParseErrorCode::MalformedNumber, span.start, "Malformed Int64: {} ({})", nb_part, err)
             }
         },
 
         _ => {
-            if nb_part.contains(".") {
-                match nb_part.parse::<f64>() {
+            if _is_float_literal(&nb_part) {
+                match nb_part.replace('_', "").parse::<f64>() {
                     Ok(value) => return Ok(Variant::from(value)),
                     Err(err) =>
-                        return error!(*line, "Malformed Float64: {} ({})", nb_part, err)
+                        return error!(ParseErrorCode::MalformedNumber, span.start, "Malformed Float64: {} ({})", nb_part, err)
                 }
             } else {
-                match nb_part.parse::<i64>() {
+                match _parse_integer::<i64>(&nb_part) {
                     Ok(value) => return Ok(Variant::from(value)),
                     Err(err) =>
-                        return error!(*line, "Malformed Int64: {} ({})", nb_part, err)
+                        return error!(ParseErrorCode::MalformedNumber, span.start, "Malformed Int64: {} ({})", nb_part, err)
                 }
             }
         }
@@ -466,48 +875,89 @@ fn _get_number(nb_part: String, string: &[u8], index: &mut usize, line: &mut usi
 }
 
 
-fn _parse_dict(string: &[u8], index: &mut usize, line: &mut usize, depth: usize) -> Result<Variant, String> {
+/// Tells whether a number without type suffix is a float rather than an integer
+fn _is_float_literal(nb_part: &str) -> bool {
+    let digits = nb_part.strip_prefix('-').unwrap_or(nb_part);
+    if _radix_prefix(digits.as_bytes(), 0).is_some() {
+        return false;
+    }
+    digits.contains(['.', 'e', 'E']) || digits == "inf" || digits == "nan"
+}
+
+
+/// Parses an integer that may have a 0x, 0o or 0b prefix and _ separators, e.g. -0xFF_FF
+fn _parse_integer<T: TryFrom<i128>>(nb_part: &str) -> Result<T, String> {
+    let digits = nb_part.replace('_', "");
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, digits.as_str())
+    };
+    let (radix, digits) = match _radix_prefix(digits.as_bytes(), 0) {
+        Some(radix) => (radix, &digits[2..]),
+        None => (10, digits)
+    };
+
+    // from_str_radix would accept a second sign
+    if digits.starts_with(['-', '+']) {
+        return Err(String::from("invalid digit found in string"));
+    }
+
+    let value = i128::from_str_radix(digits, radix).map_err(|err| err.to_string())?;
+    _fit_integer(if negative { -value } else { value })
+}
+
+
+fn _fit_integer<T: TryFrom<i128>>(value: i128) -> Result<T, String> {
+    T::try_from(value).map_err(|_| String::from("number too large to fit in target type"))
+}
+
+
+fn _parse_dict(string: &[u8], pos: &mut Position, depth: usize, state: &mut ParseState, anchor: Option<String>) -> Result<Variant, ParseError> {
     let mut first = true;
     let mut dict = Dictionary::new();
+
+    if let Some(name) = anchor {
+        state.anchors.insert(name, Variant::from(dict.clone()));
+    }
     
     loop {
         // Look for end of dict or comma
-        let (la_token, la_index, la_line) = lookahead_token(string, *index, *line);
+        let (la_token, la_pos) = lookahead_token(string, *pos);
 
-        if let Token::CloseCurlyBracket = la_token {
-            *index = la_index;
-            *line  = la_line;
+        if let TokenKind::CloseCurlyBracket = la_token.kind {
+            *pos = la_pos;
             return Ok(Variant::from(dict));
         }
 
-        if let Token::Comma = la_token {
-            if first { return error!(la_line, "Unexpected comma"); }
-            *index = la_index;
-            *line = la_line;
+        if let TokenKind::Comma = la_token.kind {
+            if first { return error!(ParseErrorCode::UnexpectedToken, la_token.span.start, "Unexpected comma"); }
+            *pos = la_pos;
+
+            // Trailing comma
+            if let TokenKind::CloseCurlyBracket = lookahead_token(string, *pos).0.kind {
+                continue;
+            }
         } else {
-            if !first { return error!(la_line, "Expected comma, found: {:?}", la_token); }
+            if !first { return error!(_token_error_code(&la_token.kind), la_token.span.start, "Expected comma, found: {:?}", la_token.kind); }
         }
 
 
         // Parse next dict entry
 
-        // Get identifier
-        let ident = match get_token(string, index, line) {
-            Token::Identifier(i) => i,
-            Token::String(s) => s,
-            token => return error!(*line, "Expected dictionary key, found: {:?}", token)
-        };
+        // Get key
+        let key = parse_key(string, pos, depth, state)?;
 
         // Check for colon
-        match get_token(string, index, line) {
-            Token::Colon => {},
-            token => return error!(*line, "Expected colon, found: {:?}", token)
+        let token = get_token(string, pos);
+        match token.kind {
+            TokenKind::Colon => {},
+            kind => return error!(_token_error_code(&kind), token.span.start, "Expected colon, found: {:?}", kind)
         }
 
         // Get variant
-        let variant = _parse(string, index, line, depth)?;
+        let variant = _parse(string, pos, depth, state, None)?;
 
-        dict.insert(ident, variant);
+        dict.insert(key, variant);
 
         // No longer the first pass
         first = false;
@@ -515,23 +965,23 @@ fn _parse_dict(string: &[u8], index: &mut usize, line: &mut usize, depth: usize)
 }
 
 
-fn _parse_array(string: &[u8], index: &mut usize, line: &mut usize, depth: usize) -> Result<Variant, String> {
+fn _parse_array(string: &[u8], pos: &mut Position, depth: usize, state: &mut ParseState, anchor: Option<String>) -> Result<Variant, ParseError> {
+    let start = *pos;
+
     // Get the array type
     let array_type = {
-        let (la_token, la_index, la_line) = lookahead_token(string, *index, *line);
+        let (la_token, la_pos) = lookahead_token(string, *pos);
 
-        if let Token::Identifier(ident) = la_token {
+        if let TokenKind::Identifier(ident) = la_token.kind {
             match get_marker_type(&ident) {
                 MarkerType::Variant => MarkerType::Variant,
                 marker => {
                     // We have a marker, so update the index to go after it
-                    *index = la_index;
-                    *line  = la_line;
+                    *pos = la_pos;
                     // Check if we have a comma after
                     // if we do, go after the comma
-                    if let (Token::Comma, la_index, la_line) = lookahead_token(string, *index, *line) {
-                        *index = la_index;
-                        *line  = la_line;
+                    if let (Token { kind: TokenKind::Comma, .. }, la_pos) = lookahead_token(string, *pos) {
+                        *pos = la_pos;
                     }
 
                     marker
@@ -544,141 +994,366 @@ fn _parse_array(string: &[u8], index: &mut usize, line: &mut usize, depth: usize
 
 
     // Parse the array
-    match array_type {
-        MarkerType::Variant => _parse_collection!(Variant; CloseBracket)(string, index, line, depth),
-        MarkerType::Float32 => _parse_collection!(PackedFloat32Array, Float, f32; CloseBracket)(string, index, line, depth),
-        MarkerType::Float64 => _parse_collection!(PackedFloat64Array, Float, f64; CloseBracket)(string, index, line, depth),
-        MarkerType::Int32 => _parse_collection!(PackedInt32Array, Int, i32; CloseBracket)(string, index, line, depth),
-        MarkerType::Int64 => _parse_collection!(PackedInt64Array, Int, i64; CloseBracket)(string, index, line, depth),
-        MarkerType::Byte => _parse_collection!(PackedByteArray, Int, u8; CloseBracket)(string, index, line, depth),
-        MarkerType::String => _parse_collection!(PackedStringArray, String, GString; CloseBracket)(string, index, line, depth),
-        MarkerType::Vector2 => _parse_collection!(PackedVector2Array, Vector2, Vector2; CloseBracket)(string, index, line, depth),
-        MarkerType::Vector3 => _parse_collection!(PackedVector3Array, Vector3, Vector3; CloseBracket)(string, index, line, depth),
-        _ => error!(*line, "Unsupported array type: {:?}", array_type)
-    }
-}
-
-
-fn lookahead_token(string: &[u8], index: usize, line: usize) -> (Token, usize, usize) {
-    let mut lookahead_index = index;
-    let mut lookahead_line = line;
-    let token = get_token(string, &mut lookahead_index, &mut lookahead_line);
-    return (token, lookahead_index, lookahead_line);
-}
-
-
-fn get_token(string: &[u8], index: &mut usize, line: &mut usize) -> Token {
-    while !_is_eof(string, *index) {
-        match string[*index] {
-            b'\n' => {*index += 1; *line += 1;},
-            b'{'  => {*index += 1; return Token::OpenCurlyBracket},
-            b'}'  => {*index += 1; return Token::CloseCurlyBracket},
-            b'['  => {*index += 1; return Token::OpenBracket},
-            b']'  => {*index += 1; return Token::CloseBracket},
-            b'('  => {*index += 1; return Token::OpenParenthesis},
-            b')'  => {*index += 1; return Token::CloseParenthesis},
-            b':'  => {*index += 1; return Token::Colon},
-            b',' | b';'  => {*index += 1; return Token::Comma}, // Commas and semicolons are considered the same
-            b'"' | b'\'' => {
-                let double_quote = string[*index] == b'"';
-                *index += 1;
-                let mut value = Vec::<u8>::new();
-
-                loop {
-                    if _is_eof(string, *index) {
-                        return Token::Error(format!("Unterminated string"));
-                    } else if (double_quote && string[*index] == b'"')
-                            || (!double_quote && string[*index] == b'\'') {
-                        *index += 1;
-                        break;
-                    } else if string[*index] == b'\\' {
-                        *index += 1;
-                        if _is_eof(string, *index) {
-                            return Token::Error(format!("Unterminated string"));
-                        }
-                        match string[*index] {
-                            b't' => value.push(b'\t'),
-                            b'n' => value.push(b'\n'),
-                            b'r' => value.push(b'\r'),
-                            b'\n' => { value.push(b'\n'); *line += 1 } // TODO: implement unicode character
-                            /*b'u' => { // Unicode escape character - see godot source code on parsing json
-                                let mut res = 0u32;
-                                for i in 0..4usize {
-                                    if _is_eof(string, *index + i) {
-                                        return Token::Error(format!("Unterminated string"));
-                                    }
+    let array = match array_type {
+        MarkerType::Variant => return _parse_collection!(Variant; CloseBracket)(string, pos, depth, state, anchor),
+        MarkerType::Float32 => _parse_collection!(PackedFloat32Array, Float, f32; CloseBracket)(string, pos, depth, state, &array_type),
+        MarkerType::Float64 => _parse_collection!(PackedFloat64Array, Float, f64; CloseBracket)(string, pos, depth, state, &array_type),
+        MarkerType::Int32 => _parse_collection!(PackedInt32Array, Int, i32; CloseBracket)(string, pos, depth, state, &array_type),
+        MarkerType::Int64 => _parse_collection!(PackedInt64Array, Int, i64; CloseBracket)(string, pos, depth, state, &array_type),
+        MarkerType::Byte => _parse_collection!(PackedByteArray, Int, u8; CloseBracket)(string, pos, depth, state, &array_type),
+        MarkerType::String => _parse_collection!(PackedStringArray, String, GString; CloseBracket)(string, pos, depth, state, &array_type),
+        MarkerType::StringName => _parse_collection!(StringNameArray, StringName, StringName; CloseBracket)(string, pos, depth, state, &array_type),
+        MarkerType::NodePath => _parse_collection!(NodePathArray, NodePath, NodePath; CloseBracket)(string, pos, depth, state, &array_type),
+        MarkerType::Vector2 => _parse_collection!(PackedVector2Array, Vector2, Vector2; CloseBracket)(string, pos, depth, state, &array_type),
+        MarkerType::Vector3 => _parse_collection!(PackedVector3Array, Vector3, Vector3; CloseBracket)(string, pos, depth, state, &array_type),
+        MarkerType::Vector4 => _parse_collection!(PackedVector4Array, Vector4, Vector4; CloseBracket)(string, pos, depth, state, &array_type),
+        MarkerType::Color => _parse_collection!(PackedColorArray, Color, Color; CloseBracket)(string, pos, depth, state, &array_type),
+        _ => error!(ParseErrorCode::UnsupportedType, start, "Unsupported array type: {:?}", array_type)
+    }?;
+
+    // Packed arrays are values, they can be registered once complete
+    if let Some(name) = anchor {
+        state.anchors.insert(name, array.clone());
+    }
+    Ok(array)
+}
 
-                                    let c = string[*index + i];
-                                    if !c.is_ascii_hexdigit() {
-                                        return Token::Error(format!("Malformed hex in string"));
-                                    }
 
-                                    if c.is_ascii_digit() {
-                                        let c = c - b'0';
-                                        res += c as u32;
-                                        value.push(c);
-                                    } else if c <= b'F' {
-                                        let c = c - b'A' + 10u8;
-                                        res += c as u32;
-                                        value.push(c);
-                                    } else {
-                                        let c = c - b'a' + 10u8;
-                                        res += c as u32;
-                                        value.push(c);
-                                    }
-                                    res <<= 4;
-                                }
-                                *index += 3;
+pub(crate) fn lookahead_token(string: &[u8], pos: Position) -> (Token, Position) {
+    let mut lookahead_pos = pos;
+    let token = get_token(string, &mut lookahead_pos);
+    return (token, lookahead_pos);
+}
 
-                                if res & 0xfffffc00 == 0xd800 {
 
-                                }
-                            },*/
-                            c => value.push(c)
-                        }
-                        *index += 1;
-                    } else {
-                        if string[*index] == b'\n' {
-                            *line += 1;
-                        }
-                        value.push(string[*index]);
-                        *index += 1;
-                    }
+pub(crate) fn get_token(string: &[u8], pos: &mut Position) -> Token {
+    _skip_ignored(string, pos);
+    let start = *pos;
+    let kind = _get_token_kind(string, pos);
+    return Token { kind, span: Span { start, end: *pos } };
+}
+
+
+/// Reads a string between quotes, pos being on the opening quote
+fn _read_string(string: &[u8], pos: &mut Position) -> TokenKind {
+    let double_quote = string[pos.offset] == b'"';
+    _advance(string, pos);
+    let mut value = Vec::<u8>::new();
+
+    loop {
+        if _is_eof(string, pos.offset) {
+            return TokenKind::Error(ParseErrorCode::UnterminatedString, format!("Unterminated string"));
+        } else if (double_quote && string[pos.offset] == b'"')
+                || (!double_quote && string[pos.offset] == b'\'') {
+            _advance(string, pos);
+            break;
+        } else if string[pos.offset] == b'\\' {
+            _advance(string, pos);
+            if let Err(err) = _parse_escape(string, pos, &mut value) {
+                return err;
+            }
+        } else {
+            value.push(string[pos.offset]);
+            _advance(string, pos);
+        }
+    }
+
+    // Only reachable with byte input, &str input is always valid UTF-8
+    match String::from_utf8(value) {
+        Ok(value) => TokenKind::String(value),
+        Err(_) => TokenKind::Error(ParseErrorCode::MalformedString, format!("Invalid UTF-8 in string"))
+    }
+}
+
+
+/// Parses the escape sequence following a backslash, and pushes the escaped character to value
+fn _parse_escape(string: &[u8], pos: &mut Position, value: &mut Vec<u8>) -> Result<(), TokenKind> {
+    if _is_eof(string, pos.offset) {
+        return Err(TokenKind::Error(ParseErrorCode::UnterminatedString, format!("Unterminated string")));
+    }
+
+    let code_point = match string[pos.offset] {
+        b't' => { _advance(string, pos); '\t' as u32 },
+        b'n' => { _advance(string, pos); '\n' as u32 },
+        b'r' => { _advance(string, pos); '\r' as u32 },
+        b'b' => { _advance(string, pos); 0x08 },
+        b'f' => { _advance(string, pos); 0x0C },
+        b'0' => { _advance(string, pos); 0 },
+        b'x' => { // \xNN
+            _advance(string, pos);
+            _parse_hex(string, pos, 2)?
+        },
+        b'u' if _is_char_at(string, pos.offset + 1, b'{') => { // \u{N...}
+            _advance(string, pos);
+            _advance(string, pos);
+            let start_offset = pos.offset;
+            while !_is_eof(string, pos.offset) && string[pos.offset].is_ascii_hexdigit() {
+                _advance(string, pos);
+            }
+            if _is_eof(string, pos.offset) {
+                return Err(TokenKind::Error(ParseErrorCode::UnterminatedString, format!("Unterminated string")));
+            }
+            if !_is_char_at(string, pos.offset, b'}') || pos.offset == start_offset || pos.offset - start_offset > 6 {
+                return Err(TokenKind::Error(ParseErrorCode::MalformedString, format!("Malformed unicode escape")));
+            }
+
+            let digits = unsafe { std::str::from_utf8_unchecked(&string[start_offset..pos.offset]) };
+            _advance(string, pos);
+            u32::from_str_radix(digits, 16).unwrap()
+        },
+        b'u' => { // \uNNNN, with UTF-16 surrogate pairs
+            _advance(string, pos);
+            let high = _parse_hex(string, pos, 4)?;
+            if (0xD800..0xDC00).contains(&high) {
+                // The low surrogate must follow right after
+                if _is_eof(string, pos.offset) || _is_eof(string, pos.offset + 1) {
+                    return Err(TokenKind::Error(ParseErrorCode::UnterminatedString, format!("Unterminated string")));
+                }
+                if !_is_char_at(string, pos.offset, b'\\') || !_is_char_at(string, pos.offset + 1, b'u') {
+                    return Err(TokenKind::Error(ParseErrorCode::MalformedString, format!("Unpaired surrogate in unicode escape")));
                 }
+                _advance(string, pos);
+                _advance(string, pos);
 
-                return Token::String(String::from_utf8(value).unwrap());
-            }
-            c if c <= 32 => *index += 1, // ignore whitespaces and other non printable characters
-            d if d.is_ascii_digit() || d == b'-' || d == b'.' => { // some sort of number
-                let start_index = *index;
-                
-                while !_is_eof(string, *index)
-                        && (string[*index].is_ascii_digit() || string[*index] == b'-' || string[*index] == b'.'
-                            || string[*index] == b'e' || string[*index] == b'E' || string[*index] == b'x'
-                            || string[*index] == b'o') {
-                    *index += 1;
+                let low = _parse_hex(string, pos, 4)?;
+                if !(0xDC00..0xE000).contains(&low) {
+                    return Err(TokenKind::Error(ParseErrorCode::MalformedString, format!("Invalid low surrogate in unicode escape")));
                 }
+                0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+            } else {
+                high
+            }
+        },
+        c => { // Quotes, backslashes, new lines and any other character escape to themselves
+            value.push(c);
+            _advance(string, pos);
+            return Ok(());
+        }
+    };
 
-                return Token::Number(String::from(
-                    unsafe { std::str::from_utf8_unchecked(&string[start_index..*index]) }
-                ));
-            },
-            c if c.is_ascii_alphabetic() || c == b'_' => { // an identifyer
-                let mut ident = Vec::<u8>::new();
-                while !_is_eof(string, *index) && (string[*index].is_ascii_alphanumeric() || string[*index] == b'_') {
-                    ident.push(string[*index]);
-                    *index += 1;
+    match char::from_u32(code_point) {
+        Some(c) => {
+            let mut buffer = [0u8; 4];
+            value.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            Ok(())
+        },
+        None => Err(TokenKind::Error(ParseErrorCode::MalformedString, format!("Invalid unicode code point {:#x}", code_point)))
+    }
+}
+
+
+fn _parse_hex(string: &[u8], pos: &mut Position, digits: usize) -> Result<u32, TokenKind> {
+    let mut res = 0u32;
+    for _ in 0..digits {
+        if _is_eof(string, pos.offset) {
+            return Err(TokenKind::Error(ParseErrorCode::UnterminatedString, format!("Unterminated string")));
+        }
+
+        let c = string[pos.offset];
+        let digit = match c {
+            b'0'..=b'9' => c - b'0',
+            b'a'..=b'f' => c - b'a' + 10,
+            b'A'..=b'F' => c - b'A' + 10,
+            _ => return Err(TokenKind::Error(ParseErrorCode::MalformedString, format!("Malformed hex in string")))
+        };
+        res = (res << 4) | digit as u32;
+        _advance(string, pos);
+    }
+    Ok(res)
+}
+
+
+/// Skips whitespaces, other non printable characters and comments
+fn _skip_ignored(string: &[u8], pos: &mut Position) {
+    while !_is_eof(string, pos.offset) {
+        if string[pos.offset] <= 32 {
+            _advance(string, pos);
+        } else if !skip_comment(string, pos) {
+            return;
+        }
+    }
+}
+
+
+/// Moves after the comment starting at pos. Returns false if there is no comment there, or if it is
+/// a block comment that is never closed.
+pub(crate) fn skip_comment(string: &[u8], pos: &mut Position) -> bool {
+    match string.get(pos.offset) {
+        Some(b'#') => _skip_line(string, pos),
+        Some(b'/') if _is_char_at(string, pos.offset + 1, b'/') => _skip_line(string, pos),
+        Some(b'/') if _is_char_at(string, pos.offset + 1, b'*') => {
+            // Look for the end of the comment before skipping it, so that an unterminated
+            // comment is reported by the tokenizer
+            let mut comment_end = pos.offset + 2;
+            while !_is_eof(string, comment_end) && !(string[comment_end] == b'*' && _is_char_at(string, comment_end + 1, b'/')) {
+                comment_end += 1;
+            }
+            if _is_eof(string, comment_end) {
+                return false;
+            }
+
+            while pos.offset < comment_end + 2 {
+                _advance(string, pos);
+            }
+        },
+        _ => return false
+    }
+    true
+}
+
+
+/// Skips everything up to the end of the line
+fn _skip_line(string: &[u8], pos: &mut Position) {
+    while !_is_eof(string, pos.offset) && string[pos.offset] != b'\n' {
+        _advance(string, pos);
+    }
+}
+
+
+fn _is_char_at(string: &[u8], index: usize, c: u8) -> bool {
+    !_is_eof(string, index) && string[index] == c
+}
+
+
+/// Moves the position one byte forward
+fn _advance(string: &[u8], pos: &mut Position) {
+    if string[pos.offset] == b'\n' {
+        pos.line += 1;
+        pos.column = 1;
+    } else if (string[pos.offset] & 0xC0) != 0x80 { // Do not count UTF-8 continuation bytes as new characters
+        pos.column += 1;
+    }
+    pos.offset += 1;
+}
+
+
+/// Moves the position to the next character, whichever it is
+pub(crate) fn skip_char(string: &[u8], pos: &mut Position) {
+    _advance(string, pos);
+    while !_is_eof(string, pos.offset) && (string[pos.offset] & 0xC0) == 0x80 {
+        _advance(string, pos);
+    }
+}
+
+
+fn _get_token_kind(string: &[u8], pos: &mut Position) -> TokenKind {
+    if _is_eof(string, pos.offset) {
+        return TokenKind::Eof;
+    }
+
+    match string[pos.offset] {
+        b'{'  => {_advance(string, pos); return TokenKind::OpenCurlyBracket},
+        b'}'  => {_advance(string, pos); return TokenKind::CloseCurlyBracket},
+        b'['  => {_advance(string, pos); return TokenKind::OpenBracket},
+        b']'  => {_advance(string, pos); return TokenKind::CloseBracket},
+        b'('  => {_advance(string, pos); return TokenKind::OpenParenthesis},
+        b')'  => {_advance(string, pos); return TokenKind::CloseParenthesis},
+        b':'  => {_advance(string, pos); return TokenKind::Colon},
+        b',' | b';'  => {_advance(string, pos); return TokenKind::Comma}, // Commas and semicolons are considered the same
+        b'"' | b'\'' => return _read_string(string, pos),
+        b'&' | b'^' if _is_char_at(string, pos.offset + 1, b'"') || _is_char_at(string, pos.offset + 1, b'\'') => {
+            let is_string_name = string[pos.offset] == b'&';
+            _advance(string, pos);
+            return match _read_string(string, pos) {
+                TokenKind::String(s) if is_string_name => TokenKind::StringName(s),
+                TokenKind::String(s) => TokenKind::NodePath(s),
+                kind => kind
+            };
+        }
+        b'-' if _is_word_at(string, pos.offset + 1, "inf") => { // negative infinity
+            for _ in 0..4 {
+                _advance(string, pos);
+            }
+            return TokenKind::Number(String::from("-inf"));
+        },
+        d if d.is_ascii_digit() || d == b'-' || d == b'.' => { // some sort of number
+            let start_offset = pos.offset;
+            if d == b'-' {
+                _advance(string, pos);
+            }
+
+            if let Some(radix) = _radix_prefix(string, pos.offset) {
+                // 0x, 0o or 0b integer. Hex digits would swallow suffixes such as B or f,
+                // those need to be separated by a space
+                _advance(string, pos);
+                _advance(string, pos);
+                while !_is_eof(string, pos.offset)
+                        && ((string[pos.offset] as char).is_digit(radix) || string[pos.offset] == b'_') {
+                    _advance(string, pos);
+                }
+            } else {
+                while !_is_eof(string, pos.offset)
+                        && (string[pos.offset].is_ascii_digit() || string[pos.offset] == b'-' || string[pos.offset] == b'.'
+                            || string[pos.offset] == b'e' || string[pos.offset] == b'E' || string[pos.offset] == b'_') {
+                    _advance(string, pos);
                 }
-                let ident = unsafe { String::from_utf8_unchecked(ident) };
+            }
 
-                return Token::Identifier(ident);
+            return TokenKind::Number(String::from(
+                unsafe { std::str::from_utf8_unchecked(&string[start_offset..pos.offset]) }
+            ));
+        },
+        b'/' if _is_char_at(string, pos.offset + 1, b'*') => {
+            return TokenKind::Error(ParseErrorCode::UnterminatedComment, format!("Unterminated comment"));
+        }
+        b'&' | b'*' => { // anchors and references
+            let is_anchor = string[pos.offset] == b'&';
+            _advance(string, pos);
+            let name = _read_identifier(string, pos);
+            if name.is_empty() {
+                return TokenKind::Error(ParseErrorCode::UnexpectedCharacter, format!("Expected a name after {}", if is_anchor { "&" } else { "*" }));
             }
-            _ => {
-                return Token::Error(format!("Unexpected character"));
+
+            return if is_anchor { TokenKind::Anchor(name) } else { TokenKind::Reference(name) };
+        }
+        c if c.is_ascii_alphabetic() || c == b'_' => { // an identifyer
+            let ident = _read_identifier(string, pos);
+            if _is_special_number(&ident) {
+                return TokenKind::Number(ident);
             }
+            return TokenKind::Identifier(ident);
+        }
+        _ => {
+            return TokenKind::Error(ParseErrorCode::UnexpectedCharacter, format!("Unexpected character"));
         }
     }
-    return Token::Eof;
+}
+
+
+fn _read_identifier(string: &[u8], pos: &mut Position) -> String {
+    let mut ident = Vec::<u8>::new();
+    while !_is_eof(string, pos.offset) && (string[pos.offset].is_ascii_alphanumeric() || string[pos.offset] == b'_') {
+        ident.push(string[pos.offset]);
+        _advance(string, pos);
+    }
+    unsafe { String::from_utf8_unchecked(ident) }
+}
+
+
+/// Returns the radix of the integer starting at the index if it has a 0x, 0o or 0b prefix.
+/// The prefix must be followed by a digit, so that 0b still reads as a zero byte.
+fn _radix_prefix(string: &[u8], index: usize) -> Option<u32> {
+    if !_is_char_at(string, index, b'0') {
+        return None;
+    }
+    let radix = match string.get(index + 1) {
+        Some(b'x') => 16,
+        Some(b'o') => 8,
+        Some(b'b') => 2,
+        _ => return None
+    };
+    match string.get(index + 2) {
+        Some(c) if (*c as char).is_digit(radix) => Some(radix),
+        _ => None
+    }
+}
+
+
+/// Returns true if the given word is at the index, and is not the start of a longer identifier
+fn _is_word_at(string: &[u8], index: usize, word: &str) -> bool {
+    let end = index + word.len();
+    string.get(index..end) == Some(word.as_bytes())
+        && !string.get(end).is_some_and(|c| c.is_ascii_alphanumeric() || *c == b'_')
 }
 
 
@@ -695,6 +1370,8 @@ fn get_marker_type(string: &str) -> MarkerType {
         "Int64"       | "int64"       | "i64" | "I" | "i" => MarkerType::Int64,
         "Byte"        | "byte"        | "B"   | "b"       => MarkerType::Byte,
         "String"      | "string"                          => MarkerType::String,
+        "StringName"  | "stringname"                      => MarkerType::StringName,
+        "NodePath"    | "nodepath"                        => MarkerType::NodePath,
         "Color"       | "color"                           => MarkerType::Color,
         "Vector2"     | "vector2"     | "vec2"            => MarkerType::Vector2,
         "Vector3"     | "vector3"     | "vec3"            => MarkerType::Vector3,
@@ -707,6 +1384,151 @@ fn get_marker_type(string: &str) -> MarkerType {
         "AABB"        | "Aabb"        | "aabb"            => MarkerType::Aabb,
         "Transform2D" | "transform2d" | "trans2d"         => MarkerType::Transform2D,
         "Transform3D" | "transform3d" | "trans3d"         => MarkerType::Transform3D,
+        "Quaternion"  | "quaternion"  | "quat"            => MarkerType::Quaternion,
+        "Basis"       | "basis"                           => MarkerType::Basis,
+        "Plane"       | "plane"                           => MarkerType::Plane,
+        "Projection"  | "projection"                      => MarkerType::Projection,
         _                                                 => MarkerType::Variant
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn parse_error(string: &str) -> ParseError {
+        match parse_ex(string, false) {
+            Ok(_) => panic!("{} should not parse", string),
+            Err(err) => err
+        }
+    }
+
+
+    fn tokens(string: &str) -> Vec<Token> {
+        let mut pos = Position::START;
+        let mut tokens = Vec::new();
+        loop {
+            let token = get_token(string.as_bytes(), &mut pos);
+            match token.kind {
+                TokenKind::Eof => return tokens,
+                TokenKind::Error(..) => {
+                    tokens.push(token);
+                    return tokens;
+                },
+                _ => tokens.push(token)
+            }
+        }
+    }
+
+
+    #[test]
+    fn token_spans() {
+        let spans: Vec<_> = tokens("{\n  \"é\": 1 }").iter()
+            .map(|token| (token.span.start.offset, token.span.start.line, token.span.start.column, token.span.end.column))
+            .collect();
+        // Columns count characters, offsets count bytes
+        assert_eq!(spans, [(0, 1, 1, 2), (4, 2, 3, 6), (8, 2, 6, 7), (10, 2, 8, 9), (12, 2, 10, 11)]);
+
+        let err = parse_error("\n  @");
+        assert_eq!(err.code, ParseErrorCode::UnexpectedCharacter);
+        assert_eq!((err.offset, err.line, err.column), (3, 2, 3));
+    }
+
+
+    #[test]
+    fn comments() {
+        let kinds: Vec<String> = tokens("# a\n{ // b\n/* c\n*/ }").iter().map(|token| format!("{:?}", token.kind)).collect();
+        assert_eq!(kinds, ["OpenCurlyBracket", "CloseCurlyBracket"]);
+
+        let err = parse_error("\n/* never closed");
+        assert_eq!(err.code, ParseErrorCode::UnterminatedComment);
+        assert_eq!((err.line, err.column), (2, 1));
+    }
+
+
+    #[test]
+    fn unicode_escapes() {
+        let kind = |string: &str| tokens(string).remove(0).kind;
+
+        assert!(matches!(kind("\"\\u00e9\\u{1F600}\\uD83D\\uDE00\""), TokenKind::String(s) if s == "é😀😀"));
+        assert!(matches!(kind("\"\\uD800x\""), TokenKind::Error(ParseErrorCode::MalformedString, _)));
+        assert!(matches!(kind("\"\\uD800\\u0041\""), TokenKind::Error(ParseErrorCode::MalformedString, _)));
+        assert!(matches!(kind("\"\\u{110000}\""), TokenKind::Error(ParseErrorCode::MalformedString, _)));
+        assert!(matches!(kind("\"\\u{}\""), TokenKind::Error(ParseErrorCode::MalformedString, _)));
+        assert!(matches!(kind("\"\\u12G4\""), TokenKind::Error(ParseErrorCode::MalformedString, _)));
+        assert!(matches!(kind("\"\\u12"), TokenKind::Error(ParseErrorCode::UnterminatedString, _)));
+    }
+
+
+    #[test]
+    fn special_number_literals() {
+        let kinds: Vec<String> = tokens("[inf, -inf, nan, -0.0, infinity]").iter().map(|token| format!("{:?}", token.kind)).collect();
+        assert_eq!(kinds, [
+            "OpenBracket", "Number(\"inf\")", "Comma", "Number(\"-inf\")", "Comma", "Number(\"nan\")", "Comma",
+            "Number(\"-0.0\")", "Comma", "Identifier(\"infinity\")", "CloseBracket"
+        ]);
+        assert!(_is_float_literal("-inf") && _is_float_literal("nan") && _is_float_literal("-0.0"));
+    }
+
+
+    #[test]
+    fn integer_literals() {
+        assert_eq!(_parse_integer::<i64>("0xFF"), Ok(255));
+        assert_eq!(_parse_integer::<i64>("-0x10"), Ok(-16));
+        assert_eq!(_parse_integer::<i64>("0o17"), Ok(15));
+        assert_eq!(_parse_integer::<i64>("0b1010_1010"), Ok(170));
+        assert_eq!(_parse_integer::<i64>("1_000_000"), Ok(1_000_000));
+        assert_eq!(_parse_integer::<i64>("-9223372036854775808"), Ok(i64::MIN));
+        assert_eq!(_parse_integer::<u8>("0xFF"), Ok(255));
+        assert!(_parse_integer::<u8>("256").is_err());
+        assert!(_parse_integer::<i64>("--1").is_err());
+        assert!(_parse_integer::<i64>("0x-1").is_err());
+
+        assert!(!_is_float_literal("0x1e5"));
+        assert!(_is_float_literal("1e5"));
+
+        // 0b without digits is a zero byte
+        let kinds: Vec<String> = tokens("0xFF_FF 0b").iter().map(|token| format!("{:?}", token.kind)).collect();
+        assert_eq!(kinds, ["Number(\"0xFF_FF\")", "Number(\"0\")", "Identifier(\"b\")"]);
+    }
+
+
+    #[test]
+    fn color_channels_out_of_range() {
+        let err = parse_error("Color(300, 0, 0)");
+        assert_eq!(err.code, ParseErrorCode::MalformedNumber);
+        assert_eq!((err.line, err.column), (1, 7));
+
+        let err = parse_error("Color(0, -1, 0)");
+        assert_eq!(err.code, ParseErrorCode::MalformedNumber);
+        assert_eq!((err.line, err.column), (1, 10));
+    }
+
+
+    #[test]
+    fn float_in_integer_vector() {
+        let err = parse_error("Vector2i(1.5f, 0)");
+        assert_eq!(err.code, ParseErrorCode::TypeMismatch);
+        assert_eq!(err.column, 10);
+
+        assert_eq!(parse_error("Vector3i(0, 1.5, 0)").code, ParseErrorCode::TypeMismatch);
+        assert_eq!(parse_error("Rect2i(0, 0, 5000000000i64, 0)").code, ParseErrorCode::MalformedNumber);
+    }
+
+
+    #[test]
+    fn repeated_anchors() {
+        let err = parse_error(&format!("{}1", "&a ".repeat(100_000)));
+        assert_eq!(err.code, ParseErrorCode::UnexpectedToken);
+        assert_eq!(err.column, 4);
+    }
+
+
+    #[test]
+    fn objects_not_allowed() {
+        let err = parse_error("MyItem { damage: 5 }");
+        assert_eq!(err.code, ParseErrorCode::ObjectNotAllowed);
+        assert_eq!(err.column, 1);
+    }
+}
@@ -0,0 +1,214 @@
+/// Layout of a stringified value, for a Wadler-style pretty printer.
+///
+/// Groups are written on a single line when they fit in the target width, and have all their
+/// lines broken otherwise. Fills put as many items as possible on each line.
+pub enum Doc {
+    Text(String),
+    /// A space, or a new line if the enclosing group is broken
+    Line,
+    /// Nothing, or a new line if the enclosing group is broken
+    SoftLine,
+    /// The first doc if the enclosing group is broken, the second one otherwise
+    IfBreak(Box<Doc>, Box<Doc>),
+    /// Indents the new lines of the doc one level deeper
+    Nest(Box<Doc>),
+    Group(Box<Doc>),
+    /// Items separated by lines, only the lines needed to fit in the width are broken
+    Fill(Vec<Doc>),
+    Concat(Vec<Doc>),
+}
+
+
+static LINE: Doc = Doc::Line;
+
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+
+#[derive(Clone, Copy)]
+enum Command<'a> {
+    Doc(&'a Doc),
+    /// The items of a fill that are not written yet
+    Fill(&'a [Doc]),
+}
+
+
+pub fn text(string: impl Into<String>) -> Doc {
+    Doc::Text(string.into())
+}
+
+
+pub fn if_break(broken: Doc, flat: Doc) -> Doc {
+    Doc::IfBreak(Box::new(broken), Box::new(flat))
+}
+
+
+pub fn nest(doc: Doc) -> Doc {
+    Doc::Nest(Box::new(doc))
+}
+
+
+pub fn group(doc: Doc) -> Doc {
+    Doc::Group(Box::new(doc))
+}
+
+
+/// Writes the doc, trying not to go past the width. Nested lines are indented with the indent string.
+pub fn render(doc: &Doc, indent: &str, width: usize) -> String {
+    let indent_width = indent.chars().count();
+    let mut output = String::new();
+    let mut column = 0;
+    let mut stack = vec![(0, Mode::Break, Command::Doc(doc))];
+
+    while let Some((level, mode, command)) = stack.pop() {
+        let doc = match command {
+            Command::Doc(doc) => doc,
+            Command::Fill(items) => {
+                let Some((first, rest)) = items.split_first() else { continue };
+                let Some(second) = rest.first() else {
+                    stack.push((level, mode, Command::Doc(first)));
+                    continue;
+                };
+
+                // Break the line between the two items only if the second one would not fit
+                let fits = _fits(width as isize - column as isize, vec![(mode, second), (Mode::Flat, &LINE), (mode, first)], &[]);
+                stack.push((level, mode, Command::Fill(rest)));
+                stack.push((level, if fits { Mode::Flat } else { Mode::Break }, Command::Doc(&LINE)));
+                stack.push((level, mode, Command::Doc(first)));
+                continue;
+            }
+        };
+
+        match doc {
+            Doc::Text(string) => {
+                output += string;
+                column += string.chars().count();
+            },
+            Doc::Line | Doc::SoftLine if mode == Mode::Flat => {
+                if let Doc::Line = doc {
+                    output.push(' ');
+                    column += 1;
+                }
+            },
+            Doc::Line | Doc::SoftLine => {
+                output.push('\n');
+                output += &indent.repeat(level);
+                column = level * indent_width;
+            },
+            Doc::IfBreak(broken, flat) => {
+                let doc = if mode == Mode::Break { broken } else { flat };
+                stack.push((level, mode, Command::Doc(doc.as_ref())));
+            },
+            Doc::Nest(doc) => stack.push((level + 1, mode, Command::Doc(doc.as_ref()))),
+            Doc::Group(doc) => {
+                let fits = mode == Mode::Flat
+                    || _fits(width as isize - column as isize, vec![(Mode::Flat, doc.as_ref())], &stack);
+                stack.push((level, if fits { Mode::Flat } else { Mode::Break }, Command::Doc(doc.as_ref())));
+            },
+            Doc::Fill(items) => stack.push((level, mode, Command::Fill(items))),
+            Doc::Concat(docs) => {
+                for doc in docs.iter().rev() {
+                    stack.push((level, mode, Command::Doc(doc)));
+                }
+            }
+        }
+    }
+
+    output
+}
+
+
+/// Tells whether the docs fit in the remaining width, up to the next line break.
+/// The rest of the stack is looked at once the docs are exhausted.
+fn _fits<'a>(mut remaining: isize, mut docs: Vec<(Mode, &'a Doc)>, rest: &[(usize, Mode, Command<'a>)]) -> bool {
+    let mut rest = rest.iter().rev();
+
+    loop {
+        if remaining < 0 {
+            return false;
+        }
+
+        let (mode, doc) = match docs.pop() {
+            Some(doc) => doc,
+            None => match rest.next() {
+                Some((_, mode, Command::Doc(doc))) => (*mode, *doc),
+                Some((_, mode, Command::Fill(items))) => {
+                    _push_fill(&mut docs, *mode, items);
+                    continue;
+                },
+                None => return true
+            }
+        };
+
+        match doc {
+            Doc::Text(string) => remaining -= string.chars().count() as isize,
+            Doc::Line | Doc::SoftLine if mode == Mode::Break => return true,
+            Doc::Line => remaining -= 1,
+            Doc::SoftLine => {},
+            Doc::IfBreak(broken, flat) => docs.push((mode, if mode == Mode::Break { broken.as_ref() } else { flat.as_ref() })),
+            Doc::Nest(doc) | Doc::Group(doc) => docs.push((mode, doc.as_ref())),
+            Doc::Fill(items) => _push_fill(&mut docs, mode, items),
+            Doc::Concat(items) => docs.extend(items.iter().rev().map(|doc| (mode, doc)))
+        }
+    }
+}
+
+
+/// Pushes the items of a fill to be measured, with lines between them
+fn _push_fill<'a>(docs: &mut Vec<(Mode, &'a Doc)>, mode: Mode, items: &'a [Doc]) {
+    for (i, item) in items.iter().enumerate().rev() {
+        docs.push((mode, item));
+        if i != 0 {
+            docs.push((mode, &LINE));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn list(items: Vec<Doc>) -> Doc {
+        let mut contents = vec![Doc::SoftLine];
+        for (i, item) in items.into_iter().enumerate() {
+            if i != 0 {
+                contents.push(text(","));
+                contents.push(Doc::Line);
+            }
+            contents.push(item);
+        }
+        group(Doc::Concat(vec![text("["), nest(Doc::Concat(contents)), Doc::SoftLine, text("]")]))
+    }
+
+
+    #[test]
+    fn groups_break_when_too_wide() {
+        let doc = list(vec![text("1"), text("2")]);
+        assert_eq!(render(&doc, "  ", 6), "[1, 2]");
+        assert_eq!(render(&doc, "  ", 5), "[\n  1,\n  2\n]");
+    }
+
+
+    #[test]
+    fn nested_groups_stay_inline_when_they_fit() {
+        let doc = list(vec![list(vec![text("1"), text("2")]), text("3")]);
+        assert_eq!(render(&doc, "  ", 11), "[[1, 2], 3]");
+        // The comma after the inner group counts, it must fit on the line as well
+        assert_eq!(render(&doc, "  ", 9), "[\n  [1, 2],\n  3\n]");
+        assert_eq!(render(&doc, "  ", 8), "[\n  [\n    1,\n    2\n  ],\n  3\n]");
+    }
+
+
+    #[test]
+    fn fills_break_only_where_needed() {
+        let doc = Doc::Fill(vec![text("1"), text("2"), text("3")]);
+        assert_eq!(render(&doc, "  ", 3), "1 2\n3");
+        assert_eq!(render(&doc, "  ", 5), "1 2 3");
+    }
+}
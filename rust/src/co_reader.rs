@@ -0,0 +1,504 @@
+use godot::prelude::*;
+
+use crate::co_parser::{self, ParseError, ParseErrorCode, Position, Token, TokenKind};
+
+
+/// What the reader found next in the input
+pub enum Event {
+    /// The input fed so far ends in the middle of the next event
+    NeedMoreData,
+    /// Start of a dictionary, with its anchor if any
+    BeginDictionary(Option<String>),
+    EndDictionary,
+    /// Start of an array, with its anchor and its type marker (e.g. "i32") if any
+    BeginArray(Option<String>, Option<String>),
+    EndArray,
+    /// Start of an object, with its anchor and its class name. Its properties follow as keys and values.
+    BeginObject(Option<String>, String),
+    EndObject,
+    /// Dictionary key or property name, followed by its value
+    Key(Variant),
+    /// Any value that is not a container (numbers, strings, constructors...), with its anchor if any
+    Value(Option<String>, Variant),
+    /// Reference to an anchored value (*name)
+    Reference(String),
+    /// The whole value has been read
+    End,
+}
+
+
+enum Container {
+    Dictionary,
+    Object,
+    Array,
+    /// Typed or packed array, with its type marker
+    TypedArray(String),
+}
+
+
+struct Frame {
+    container: Container,
+    /// No element has been read yet
+    first: bool,
+    /// A key has been read, its value comes next
+    after_key: bool,
+}
+
+
+/// Pull parser for CE strings given in chunks, e.g. read from a file or a network buffer.
+///
+/// Values are read one event at a time, without building the whole value. When the input
+/// fed so far ends in the middle of an event, NeedMoreData is returned and the same event
+/// is read again once more input is fed.
+pub struct Reader {
+    /// Input that has not been read yet
+    buffer: Vec<u8>,
+    /// Position in the buffer. The offset starts at the beginning of the buffer, the line and column at the beginning of the input.
+    pos: Position,
+    /// Number of bytes read and dropped from the buffer
+    consumed: usize,
+    /// Start of the token that ran into the end of the input, the next event cannot be read until it is complete
+    pending: Option<Position>,
+    /// Offset in the buffer up to which the input has been looked at for the end of the pending token
+    pending_checked: usize,
+    /// No more input will be fed
+    finished: bool,
+    stack: Vec<Frame>,
+    state: co_parser::ParseState,
+    done: bool,
+    /// Once an error is found, it is returned by every call to next
+    error: Option<ParseError>,
+}
+
+
+impl Default for Reader {
+    fn default() -> Self {
+        Reader::new()
+    }
+}
+
+
+impl Reader {
+    pub fn new() -> Self {
+        Reader {
+            buffer: Vec::new(),
+            pos: Position::START,
+            consumed: 0,
+            pending: None,
+            pending_checked: 0,
+            finished: false,
+            stack: Vec::new(),
+            state: co_parser::ParseState::new(false),
+            done: false,
+            error: None
+        }
+    }
+
+
+    /// Adds input after the one already fed
+    pub fn feed(&mut self, bytes: &[u8]) {
+        // Drop what has already been read, so that the buffer only holds the current event
+        self.buffer.drain(..self.pos.offset);
+        self.consumed += self.pos.offset;
+        if let Some(pending) = &mut self.pending {
+            pending.offset -= self.pos.offset;
+        }
+        self.pending_checked -= self.pos.offset.min(self.pending_checked);
+        self.pos.offset = 0;
+
+        self.buffer.extend_from_slice(bytes);
+    }
+
+
+    /// Allows objects in the input, see co_parser::parse_ex
+    pub fn set_allow_objects(&mut self, allow_objects: bool) {
+        self.state.allow_objects = allow_objects;
+    }
+
+
+    /// Tells the reader that the whole input has been fed
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+
+    /// Number of containers the reader is in
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+
+    /// Byte offset of the reader in the whole input
+    pub fn offset(&self) -> usize {
+        self.consumed + self.pos.offset
+    }
+
+
+    pub fn next(&mut self) -> Result<Event, ParseError> {
+        if let Some(err) = &self.error {
+            return Err(err.clone());
+        }
+        if self.done {
+            return self._read_end();
+        }
+
+        // Reading the event again is only worth it once the token that stopped it is complete,
+        // otherwise a long value fed in small chunks would be read again for every chunk.
+        // The token is not even lexed again until a byte that could end it is fed.
+        if let Some(pending) = self.pending {
+            let fed = &self.buffer[self.pending_checked.min(self.buffer.len())..];
+            let may_end = fed.iter().any(|byte| _may_end_token(&self.buffer[pending.offset..], *byte));
+            self.pending_checked = self.buffer.len();
+            if !self.finished && !may_end {
+                return Ok(Event::NeedMoreData);
+            }
+            if self._unsettled_token(pending, 1).is_some() {
+                return Ok(Event::NeedMoreData);
+            }
+            self.pending = None;
+        }
+
+        let mut pos = self.pos;
+        let event = match _read(&self.buffer, &mut pos, &self.stack, &mut self.state) {
+            Ok(event) => match self._unsettled_token(pos, 1) {
+                // Events are only returned once the input that follows them is known,
+                // as it could change them (e.g. the 1 of 1f, or the 2 of 25)
+                Some(pending) => {
+                    self._wait_for(pending);
+                    return Ok(Event::NeedMoreData);
+                },
+                None => event
+            },
+            Err(err) => {
+                // Errors caused by the input stopping in the middle of a value go away with more input
+                if let Some(pending) = self._unsettled_token(Position { offset: err.offset, line: err.line, column: err.column }, 2) {
+                    self._wait_for(pending);
+                    return Ok(Event::NeedMoreData);
+                }
+                return Err(self._fail(err));
+            }
+        };
+        self.pos = pos;
+
+        match &event {
+            Event::BeginDictionary(_) => self._push(Container::Dictionary),
+            Event::BeginObject(..) => self._push(Container::Object),
+            Event::BeginArray(_, Some(array_type)) => self._push(Container::TypedArray(array_type.clone())),
+            Event::BeginArray(_, None) => self._push(Container::Array),
+            Event::EndDictionary | Event::EndObject | Event::EndArray => {
+                self.stack.pop();
+                self.done = self.stack.is_empty();
+            },
+            Event::Key(_) => {
+                if let Some(frame) = self.stack.last_mut() {
+                    frame.first = false;
+                    frame.after_key = true;
+                }
+            },
+            Event::Value(..) | Event::Reference(_) => {
+                self._end_element();
+                self.done = self.stack.is_empty();
+            },
+            Event::NeedMoreData | Event::End => {}
+        }
+
+        Ok(event)
+    }
+
+
+    /// Once the whole value is read, only whitespaces and comments may follow it
+    fn _read_end(&mut self) -> Result<Event, ParseError> {
+        let mut pos = self.pos;
+        let token = co_parser::get_token(&self.buffer, &mut pos);
+        match token.kind {
+            TokenKind::Eof => Ok(Event::End),
+            // e.g. a comment that is not over yet, it is checked again with the next input
+            _ if self._unsettled_token(token.span.start, 1).is_some() => Ok(Event::End),
+            TokenKind::Error(code, message) => Err(self._fail(ParseError::new(code, token.span.start, message))),
+            kind => Err(self._fail(ParseError::new(ParseErrorCode::UnexpectedToken, token.span.start, format!("Unexpected data after the value: {:?}", kind))))
+        }
+    }
+
+
+    /// Stops reading the next event until the token starting at the position is complete
+    fn _wait_for(&mut self, pending: Position) {
+        self.pending = Some(pending);
+        self.pending_checked = self.buffer.len();
+    }
+
+
+    /// Records the error, which is then returned by every call to next
+    fn _fail(&mut self, err: ParseError) -> ParseError {
+        let err = ParseError { offset: err.offset + self.consumed, ..err };
+        self.error = Some(err.clone());
+        err
+    }
+
+
+    fn _push(&mut self, container: Container) {
+        self._end_element();
+        self.stack.push(Frame { container, first: true, after_key: false });
+    }
+
+
+    /// Marks that an element of the current container has been read
+    fn _end_element(&mut self) {
+        if let Some(frame) = self.stack.last_mut() {
+            frame.first = false;
+            frame.after_key = false;
+        }
+    }
+
+
+    /// Returns the start of the first of the given number of tokens after the position that is not complete,
+    /// i.e. that more input could change. None if they are all complete.
+    fn _unsettled_token(&self, mut pos: Position, tokens: usize) -> Option<Position> {
+        if self.finished {
+            return None;
+        }
+
+        let len = self.buffer.len();
+        for _ in 0..tokens {
+            let token = co_parser::get_token(&self.buffer, &mut pos);
+            let complete = match token.kind {
+                TokenKind::Eof => false,
+                TokenKind::Error(ParseErrorCode::UnterminatedString | ParseErrorCode::UnterminatedComment, _) => false,
+                // An unexpected character may be the first half of a comment or of a UTF-8 character
+                TokenKind::Error(..) => token.span.start.offset + 1 < len && token.span.end.offset < len,
+                TokenKind::Identifier(_) | TokenKind::Number(_) | TokenKind::Anchor(_) | TokenKind::Reference(_) => token.span.end.offset < len,
+                _ => true
+            };
+            if !complete {
+                return Some(token.span.start);
+            }
+        }
+        None
+    }
+}
+
+
+/// Reads the next event, moving pos after it
+fn _read(string: &[u8], pos: &mut Position, stack: &[Frame], state: &mut co_parser::ParseState) -> Result<Event, ParseError> {
+    let Some(frame) = stack.last() else {
+        return _read_value(string, pos, 0, state);
+    };
+    if frame.after_key {
+        return _read_value(string, pos, stack.len(), state);
+    }
+
+    let (is_dict, end_event) = match frame.container {
+        Container::Dictionary => (true, Event::EndDictionary),
+        Container::Object => (true, Event::EndObject),
+        Container::Array | Container::TypedArray(_) => (false, Event::EndArray)
+    };
+    let is_closing = |kind: &TokenKind| match kind {
+        TokenKind::CloseCurlyBracket => is_dict,
+        TokenKind::CloseBracket => !is_dict,
+        _ => false
+    };
+
+    // Look for the end of the container or the comma before the next element
+    let (la_token, la_pos) = co_parser::lookahead_token(string, *pos);
+    if is_closing(&la_token.kind) {
+        *pos = la_pos;
+        return Ok(end_event);
+    }
+
+    if let TokenKind::Comma = la_token.kind {
+        if frame.first {
+            return Err(ParseError::new(ParseErrorCode::UnexpectedToken, la_token.span.start, String::from("Unexpected comma")));
+        }
+        *pos = la_pos;
+
+        // Trailing comma
+        let (la_token, la_pos) = co_parser::lookahead_token(string, *pos);
+        if is_closing(&la_token.kind) {
+            *pos = la_pos;
+            return Ok(end_event);
+        }
+    } else if !frame.first {
+        return Err(_unexpected_token(la_token.kind, la_token.span.start, "comma"));
+    }
+
+    match &frame.container {
+        Container::Dictionary | Container::Object => {
+            let key = co_parser::parse_key(string, pos, stack.len(), state)?;
+            let token = co_parser::get_token(string, pos);
+            match token.kind {
+                TokenKind::Colon => Ok(Event::Key(key)),
+                kind => Err(_unexpected_token(kind, token.span.start, "colon"))
+            }
+        },
+        Container::Array => _read_value(string, pos, stack.len(), state),
+        Container::TypedArray(array_type) => {
+            let element = co_parser::parse_array_element(string, pos, stack.len(), state, array_type)?;
+            Ok(Event::Value(None, element))
+        }
+    }
+}
+
+
+/// Reads the start of a container, or a whole value if it is not one
+fn _read_value(string: &[u8], pos: &mut Position, depth: usize, state: &mut co_parser::ParseState) -> Result<Event, ParseError> {
+    if depth > crate::MAX_RECURSION_DEPTH {
+        return Err(ParseError::new(ParseErrorCode::MaxDepth, *pos, String::from("Reached max recursion depth")));
+    }
+
+    let (mut la_token, mut la_pos) = co_parser::lookahead_token(string, *pos);
+    let mut anchor = None;
+    if let TokenKind::Anchor(name) = la_token.kind {
+        anchor = Some(name);
+        *pos = la_pos;
+        (la_token, la_pos) = co_parser::lookahead_token(string, *pos);
+    }
+
+    match la_token.kind {
+        TokenKind::OpenCurlyBracket => {
+            *pos = la_pos;
+            Ok(Event::BeginDictionary(anchor))
+        },
+        TokenKind::OpenBracket => {
+            *pos = la_pos;
+
+            // Typed arrays start with their type, which may be followed by a comma
+            let (la_token, la_pos) = co_parser::lookahead_token(string, *pos);
+            let TokenKind::Identifier(ident) = la_token.kind else {
+                return Ok(Event::BeginArray(anchor, None));
+            };
+            if !co_parser::is_array_marker(&ident) {
+                return Ok(Event::BeginArray(anchor, None));
+            }
+            if co_parser::array_element_type(&ident).is_none() {
+                return Err(ParseError::new(ParseErrorCode::UnsupportedType, la_token.span.start, format!("Unsupported array type: {}", ident)));
+            }
+
+            *pos = la_pos;
+            if let (Token { kind: TokenKind::Comma, .. }, la_pos) = co_parser::lookahead_token(string, *pos) {
+                *pos = la_pos;
+            }
+            Ok(Event::BeginArray(anchor, Some(ident)))
+        },
+        TokenKind::Reference(name) if anchor.is_none() => {
+            *pos = la_pos;
+            Ok(Event::Reference(name))
+        },
+        TokenKind::Identifier(ident) if !co_parser::is_keyword(&ident) && !co_parser::is_array_marker(&ident)
+                && matches!(co_parser::lookahead_token(string, la_pos).0.kind, TokenKind::OpenCurlyBracket) => {
+            // An identifier followed by a curly bracket is an object
+            if !state.allow_objects {
+                return Err(ParseError::new(ParseErrorCode::ObjectNotAllowed, la_token.span.start, format!("Objects are not allowed: {}", ident)));
+            }
+            *pos = co_parser::lookahead_token(string, la_pos).1;
+            Ok(Event::BeginObject(anchor, ident))
+        },
+        _ => {
+            let value = co_parser::parse_value(string, pos, depth, state)?;
+            if let Some(name) = &anchor {
+                state.anchors.insert(name.clone(), value.clone());
+            }
+            Ok(Event::Value(anchor, value))
+        }
+    }
+}
+
+
+/// Tells whether the byte, fed after the start of an incomplete token, could complete it
+fn _may_end_token(token: &[u8], byte: u8) -> bool {
+    match token {
+        // Strings, StringNames and NodePaths
+        [b'"' | b'\'', ..] | [b'&' | b'^', b'"' | b'\'', ..] => matches!(byte, b'"' | b'\'' | b'\n'),
+        [b'/', b'*', ..] => byte == b'/',
+        // Identifiers, numbers, anchors and references end with the first byte that cannot be part of them
+        _ => !(byte.is_ascii_alphanumeric() || byte == b'_')
+    }
+}
+
+
+fn _unexpected_token(kind: TokenKind, pos: Position, expected: &str) -> ParseError {
+    let code = match kind {
+        TokenKind::Eof => ParseErrorCode::UnexpectedEof,
+        TokenKind::Error(code, _) => code,
+        _ => ParseErrorCode::UnexpectedToken
+    };
+    ParseError::new(code, pos, format!("Expected {}, found: {:?}", expected, kind))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn waits_for_the_pending_token() {
+        let mut reader = Reader::new();
+        reader.feed(b"[ \"abc");
+        assert!(matches!(reader.next(), Ok(Event::NeedMoreData)));
+        assert_eq!(reader.pending.map(|pos| pos.offset), Some(2));
+
+        // The string is still not complete, the array is not read again
+        reader.feed(b"def");
+        assert!(matches!(reader.next(), Ok(Event::NeedMoreData)));
+        assert_eq!(reader.pending.map(|pos| pos.offset), Some(2));
+
+        reader.feed(b"\"]");
+        assert!(matches!(reader.next(), Ok(Event::BeginArray(None, None))));
+        assert!(reader.pending.is_none());
+    }
+
+
+    #[test]
+    fn only_looks_at_new_input_for_the_end_of_the_pending_token() {
+        let mut reader = Reader::new();
+        reader.feed(b"[ \"abc");
+        assert!(matches!(reader.next(), Ok(Event::NeedMoreData)));
+        assert_eq!(reader.pending_checked, 6);
+
+        // Without a quote in the new input, the string cannot be over
+        assert!(_may_end_token(b"\"abc", b'"'));
+        assert!(!_may_end_token(b"\"abc", b'd'));
+        assert!(_may_end_token(b"&'abc", b'\''));
+        assert!(_may_end_token(b"/* a", b'/'));
+        assert!(!_may_end_token(b"/* a", b'*'));
+        assert!(_may_end_token(b"12", b','));
+        assert!(!_may_end_token(b"12", b'5'));
+
+        reader.feed(b"def");
+        assert!(matches!(reader.next(), Ok(Event::NeedMoreData)));
+        assert_eq!(reader.pending_checked, 9);
+    }
+
+
+    #[test]
+    fn rejects_input_after_the_value() {
+        let mut reader = Reader::new();
+        reader.feed(b"{} # comment\n/* comment */ x");
+        reader.finish();
+        assert!(matches!(reader.next(), Ok(Event::BeginDictionary(None))));
+        assert!(matches!(reader.next(), Ok(Event::EndDictionary)));
+        let err = reader.next().err().unwrap();
+        assert_eq!(err.code, ParseErrorCode::UnexpectedToken);
+        assert_eq!((err.line, err.column, err.offset), (2, 15, 27));
+        assert!(reader.next().is_err());
+
+        // Comments and whitespaces are fine, even when they are fed after the end
+        let mut reader = Reader::new();
+        reader.feed(b"[] /* a");
+        assert!(matches!(reader.next(), Ok(Event::BeginArray(None, None))));
+        assert!(matches!(reader.next(), Ok(Event::NeedMoreData)));
+        reader.feed(b" */ ");
+        assert!(matches!(reader.next(), Ok(Event::EndArray)));
+        assert!(matches!(reader.next(), Ok(Event::End)));
+        reader.feed(b"# a");
+        assert!(matches!(reader.next(), Ok(Event::End)));
+        reader.feed(b"\n]");
+        assert!(reader.next().is_err());
+
+        let mut reader = Reader::new();
+        reader.feed(b"[] /* a");
+        reader.finish();
+        reader.next().unwrap();
+        reader.next().unwrap();
+        assert_eq!(reader.next().err().unwrap().code, ParseErrorCode::UnterminatedComment);
+    }
+}
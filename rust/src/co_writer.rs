@@ -0,0 +1,331 @@
+use godot::prelude::*;
+
+use crate::co_create::{self, StringifyOptions};
+use crate::co_parser;
+
+
+enum Container {
+    Dictionary,
+    Object,
+    Array,
+    /// Typed or packed array, with its type marker (e.g. "i32")
+    TypedArray(String),
+}
+
+
+struct Frame {
+    container: Container,
+    /// Number of elements written
+    len: usize,
+    /// A key has been written, its value comes next
+    after_key: bool,
+    /// The integer value of the last key is written in hexadecimal, see StringifyOptions::hex_keys
+    hex_key: bool,
+}
+
+
+/// Writes a CE string piece by piece, without building the whole value.
+///
+/// Containers are opened and closed explicitly, and everything else is written with value.
+/// The text is kept until take_output is called, so that it can be sent wherever it is needed.
+pub struct Writer {
+    options: StringifyOptions,
+    stack: Vec<Frame>,
+    /// Text written since the last call to take_output
+    output: String,
+    done: bool,
+}
+
+
+impl Default for Writer {
+    fn default() -> Self {
+        Writer::new(StringifyOptions::default())
+    }
+}
+
+
+impl Writer {
+    pub fn new(options: StringifyOptions) -> Self {
+        Writer {
+            options,
+            stack: Vec::new(),
+            output: String::new(),
+            done: false
+        }
+    }
+
+
+    pub fn set_options(&mut self, options: StringifyOptions) {
+        self.options = options;
+    }
+
+
+    /// Returns the text written since the last call, and forgets it
+    pub fn take_output(&mut self) -> String {
+        std::mem::take(&mut self.output)
+    }
+
+
+    /// Length of the text that take_output would return
+    pub fn output_len(&self) -> usize {
+        self.output.len()
+    }
+
+
+    /// Tells whether the whole value has been written
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+
+    pub fn begin_dictionary(&mut self) -> Result<(), String> {
+        self._begin_element(None)?;
+        self.output.push('{');
+        self._push(Container::Dictionary);
+        Ok(())
+    }
+
+
+    pub fn end_dictionary(&mut self) -> Result<(), String> {
+        self._end(|container| matches!(container, Container::Dictionary), '}', "dictionary")
+    }
+
+
+    /// Opens an object of the given class, its properties are written as keys and values
+    pub fn begin_object(&mut self, class_name: &str) -> Result<(), String> {
+        // Type names such as Vector2 would be read as a constructor
+        if !co_create::is_valid_identifier(class_name) || co_parser::is_array_marker(class_name) {
+            return Err(format!("Invalid class name: {:?}", class_name));
+        }
+
+        self._begin_element(None)?;
+        self.output += class_name;
+        self.output += " {";
+        self._push(Container::Object);
+        Ok(())
+    }
+
+
+    pub fn end_object(&mut self) -> Result<(), String> {
+        self._end(|container| matches!(container, Container::Object), '}', "object")
+    }
+
+
+    pub fn begin_array(&mut self) -> Result<(), String> {
+        self._begin_element(None)?;
+        self.output.push('[');
+        self._push(Container::Array);
+        Ok(())
+    }
+
+
+    /// Opens a typed array, e.g. [i32, ...]. Its elements must all have the type of the array.
+    pub fn begin_typed_array(&mut self, array_type: &str) -> Result<(), String> {
+        if co_parser::array_element_type(array_type).is_none() {
+            return Err(format!("Unsupported array type: {}", array_type));
+        }
+
+        self._begin_element(None)?;
+        self.output.push('[');
+        self.output += array_type;
+        self._push(Container::TypedArray(String::from(array_type)));
+        Ok(())
+    }
+
+
+    pub fn end_array(&mut self) -> Result<(), String> {
+        self._end(|container| matches!(container, Container::Array | Container::TypedArray(_)), ']', "array")
+    }
+
+
+    /// Writes a dictionary key or a property name, its value must be written next
+    pub fn key(&mut self, key: &Variant) -> Result<(), String> {
+        let Some(frame) = self.stack.last_mut() else {
+            return Err(String::from("Keys can only be written in dictionaries and objects"));
+        };
+        if !matches!(frame.container, Container::Dictionary | Container::Object) {
+            return Err(String::from("Keys can only be written in dictionaries and objects"));
+        }
+        if frame.after_key {
+            return Err(String::from("Expected the value of the previous key"));
+        }
+        frame.after_key = true;
+        frame.hex_key = co_create::is_hex_key(key, &self.options);
+        let len = frame.len;
+
+        self._separator(len, false);
+        self.output += &co_create::stringify_key_with_options(key, &self.options).to_string();
+        self.output += if self.options.indent.is_empty() { ":" } else { ": " };
+        Ok(())
+    }
+
+
+    /// Writes a whole value, e.g. a number, a vector or a dictionary that is small enough to be built
+    pub fn value(&mut self, variant: &Variant) -> Result<(), String> {
+        let hex_key = self.stack.last().is_some_and(|frame| frame.after_key && frame.hex_key);
+        self._begin_element(Some(variant))?;
+
+        let text = if hex_key && variant.get_type() == VariantType::Int {
+            co_create::format_hex(i64::from_variant(variant))
+        } else {
+            co_create::stringify_with_options(variant, &self.options).to_string()
+        };
+        if self.options.indent.is_empty() || self.stack.is_empty() {
+            self.output += &text;
+        } else {
+            // Indent the lines of nested values
+            self.output += &text.replace('\n', &format!("\n{}", self.options.indent.repeat(self.stack.len())));
+        }
+
+        self.done = self.stack.is_empty();
+        Ok(())
+    }
+
+
+    /// Checks that a value can be written, and writes what comes before it
+    fn _begin_element(&mut self, variant: Option<&Variant>) -> Result<(), String> {
+        let Some(frame) = self.stack.last_mut() else {
+            if self.done {
+                return Err(String::from("The whole value has already been written"));
+            }
+            return Ok(());
+        };
+
+        let (len, typed) = match &frame.container {
+            Container::Dictionary | Container::Object => {
+                if !frame.after_key {
+                    return Err(String::from("Expected a key before the value"));
+                }
+                frame.after_key = false;
+                frame.len += 1;
+                return Ok(());
+            },
+            Container::Array => (frame.len, false),
+            Container::TypedArray(array_type) => {
+                match variant {
+                    Some(variant) => co_parser::check_array_element(array_type, variant)?,
+                    None => return Err(String::from("Typed arrays cannot hold containers"))
+                }
+                (frame.len, true)
+            }
+        };
+        frame.len += 1;
+
+        // The type of typed arrays is followed by a comma as well
+        self._separator(len, typed);
+        Ok(())
+    }
+
+
+    /// Writes the comma and the indentation before the element
+    fn _separator(&mut self, len: usize, typed: bool) {
+        if len > 0 || typed {
+            self.output.push(',');
+        }
+        if !self.options.indent.is_empty() {
+            self.output.push('\n');
+            self.output += &self.options.indent.repeat(self.stack.len());
+        }
+    }
+
+
+    fn _push(&mut self, container: Container) {
+        self.stack.push(Frame { container, len: 0, after_key: false, hex_key: false });
+    }
+
+
+    fn _end(&mut self, is_container: fn(&Container) -> bool, closing: char, name: &str) -> Result<(), String> {
+        match self.stack.last() {
+            Some(frame) if is_container(&frame.container) => {
+                if frame.after_key {
+                    return Err(String::from("Expected the value of the previous key"));
+                }
+                let len = frame.len;
+                self.stack.pop();
+
+                if len > 0 && !self.options.indent.is_empty() {
+                    if self.options.trailing_commas {
+                        self.output.push(',');
+                    }
+                    self.output.push('\n');
+                    self.output += &self.options.indent.repeat(self.stack.len());
+                }
+                self.output.push(closing);
+
+                self.done = self.stack.is_empty();
+                Ok(())
+            },
+            _ => Err(format!("No {} to end", name))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn compact() -> StringifyOptions {
+        StringifyOptions { indent: String::new(), ..StringifyOptions::default() }
+    }
+
+
+    #[test]
+    fn nested_containers() {
+        let mut writer = Writer::default();
+        writer.begin_array().unwrap();
+        writer.begin_array().unwrap();
+        writer.end_array().unwrap();
+        writer.begin_object("Node").unwrap();
+        writer.end_object().unwrap();
+        assert!(!writer.is_done());
+        writer.end_array().unwrap();
+        assert!(writer.is_done());
+        assert_eq!(writer.take_output(), "[\n    [],\n    Node {}\n]");
+        assert_eq!(writer.output_len(), 0);
+
+        assert!(writer.begin_array().is_err());
+    }
+
+
+    #[test]
+    fn ends_only_the_open_container() {
+        let mut writer = Writer::new(compact());
+        assert!(writer.end_array().is_err());
+        writer.begin_dictionary().unwrap();
+        assert!(writer.end_array().is_err());
+        assert!(writer.end_object().is_err());
+        // Values of a dictionary need a key first
+        assert!(writer.begin_array().is_err());
+        writer.end_dictionary().unwrap();
+        assert_eq!(writer.take_output(), "{}");
+    }
+
+
+    #[test]
+    fn typed_arrays() {
+        let mut writer = Writer::new(compact());
+        assert!(writer.begin_typed_array("i33").is_err());
+        writer.begin_typed_array("i32").unwrap();
+        assert!(writer.begin_array().is_err());
+        assert!(writer.begin_dictionary().is_err());
+        writer.end_array().unwrap();
+        assert_eq!(writer.take_output(), "[i32]");
+    }
+
+
+    #[test]
+    fn class_names() {
+        let mut writer = Writer::new(compact());
+        for class_name in ["", "a b", "1Node", "Node{", "null", "Vector2", "i32"] {
+            assert!(writer.begin_object(class_name).is_err(), "{:?}", class_name);
+        }
+        assert_eq!(writer.output_len(), 0);
+        assert!(!writer.is_done());
+
+        writer.begin_object("MyItem").unwrap();
+        writer.end_object().unwrap();
+        assert_eq!(writer.take_output(), "MyItem {}");
+    }
+}
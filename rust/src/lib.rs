@@ -1,13 +1,41 @@
 mod co_parser;
 mod co_create;
+mod co_cst;
+mod co_object;
+mod co_pretty;
+mod co_binary;
+mod co_reader;
+mod co_writer;
+mod co_file;
+mod co_import;
+mod co_highlight;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicI64, Ordering};
 
 use godot::prelude::*;
-use godot::engine::Engine;
+use godot::engine::{
+    ConfigFile, Engine, FileAccess, StreamPeer, IResourceFormatLoader, IResourceFormatSaver, IEditorExportPlugin, IEditorImportPlugin, IEditorSyntaxHighlighter,
+    EditorExportPlugin, EditorImportPlugin, EditorInterface, EditorSyntaxHighlighter, ResourceFormatLoader, ResourceFormatSaver, ResourceLoader, ResourceSaver, WorkerThreadPool
+};
+use godot::engine::global::Error;
+use godot::engine::resource_saver::SaverFlags;
+
+use co_highlight::{Region, Style};
+use co_parser::ParseErrorCode;
 
 
 pub const MAX_RECURSION_DEPTH: usize = 1024;
 
 
+thread_local! {
+    /// The resource format loader and saver, kept to be unregistered when the extension is unloaded
+    static RESOURCE_FORMATS: RefCell<Option<(Gd<CerealResourceLoader>, Gd<CerealResourceSaver>)>> = RefCell::new(None);
+}
+
+
 struct CerealObjectExt;
 
 #[gdextension]
@@ -20,6 +48,13 @@ unsafe impl ExtensionLibrary for CerealObjectExt {
                 StringName::from("CerealObject"),
                 CerealObject::new_alloc().upcast(),
             );
+
+            // Lets load() and ResourceSaver.save() handle .ce files
+            let loader = CerealResourceLoader::new_gd();
+            let saver = CerealResourceSaver::new_gd();
+            ResourceLoader::singleton().add_resource_format_loader(loader.clone().upcast());
+            ResourceSaver::singleton().add_resource_format_saver(saver.clone().upcast());
+            RESOURCE_FORMATS.with(|formats| *formats.borrow_mut() = Some((loader, saver)));
         }
     }
 
@@ -30,6 +65,11 @@ unsafe impl ExtensionLibrary for CerealObjectExt {
             Engine::singleton().unregister_singleton(
                 StringName::from("CerealObject")
             );
+
+            if let Some((loader, saver)) = RESOURCE_FORMATS.with(|formats| formats.borrow_mut().take()) {
+                ResourceLoader::singleton().remove_resource_format_loader(loader.upcast());
+                ResourceSaver::singleton().remove_resource_format_saver(saver.upcast());
+            }
         }
     }
 }
@@ -49,8 +89,79 @@ impl CerealObject {
 
     #[func]
     /// Attempts to parse the cereal_object provided and returns the parsed data. Returns null if parse failed.
-    fn parse_string(&mut self, cereal_object: GString) -> Variant {
-        co_parser::parse(cereal_object.to_string())
+    /// Objects are only created if allow_objects is true, as they can run any script: only allow them for trusted data.
+    fn parse_string(&mut self, cereal_object: GString, #[opt(default = false)] allow_objects: bool) -> Variant {
+        co_parser::parse(cereal_object.to_string(), allow_objects)
+    }
+
+
+    #[func]
+    /// Parses the cereal_object provided and returns a CerealObjectParseResult holding either the data or the error.
+    fn parse_string_ex(&mut self, cereal_object: GString, #[opt(default = false)] allow_objects: bool) -> Gd<CerealObjectParseResult> {
+        CerealObjectParseResult::from_result(co_parser::parse_ex(&cereal_object.to_string(), allow_objects))
+    }
+
+
+    #[func]
+    /// Reads and parses the CE file at the path. Returns null if it could not be read or parsed.
+    fn load_file(&mut self, path: GString, #[opt(default = false)] allow_objects: bool) -> Variant {
+        match co_file::load(&path.to_string(), allow_objects) {
+            Ok(data) => data,
+            Err(co_file::LoadError::File(err)) => {
+                godot_error!("[CerealObject] Cannot read {}: {:?}", path, err);
+                Variant::nil()
+            },
+            Err(co_file::LoadError::Parse(err)) => {
+                godot_error!("[CerealObject] {}: {}", path, err);
+                Variant::nil()
+            }
+        }
+    }
+
+
+    #[func]
+    /// Reads and parses the CE file at the path, and returns a CerealObjectParseResult holding either the data or the error.
+    fn load_file_ex(&mut self, path: GString, #[opt(default = false)] allow_objects: bool) -> Gd<CerealObjectParseResult> {
+        let path = path.to_string();
+        CerealObjectParseResult::from_load_result(co_file::load(&path, allow_objects), &path)
+    }
+
+
+    #[func]
+    /// Parses the cereal_object provided on the WorkerThreadPool. The returned CerealTask emits completed
+    /// with a CerealObjectParseResult once the parse is done.
+    fn parse_string_async(&mut self, cereal_object: GString, #[opt(default = false)] allow_objects: bool) -> Gd<CerealTask> {
+        CerealTask::start(AsyncJob::Parse(cereal_object.to_string()), allow_objects)
+    }
+
+
+    #[func]
+    /// Reads and parses the CE file at the path on the WorkerThreadPool, see parse_string_async.
+    fn load_file_async(&mut self, path: GString, #[opt(default = false)] allow_objects: bool) -> Gd<CerealTask> {
+        CerealTask::start(AsyncJob::Load(path.to_string()), allow_objects)
+    }
+
+
+    #[func]
+    /// Stringifies the variant with the given options (see stringify_ex) and writes it to the file at the path.
+    /// The file is replaced only once the whole text is written.
+    fn save_file(&mut self, path: GString, variant: Variant, #[opt(default = Dictionary::new())] options: Dictionary) -> Error {
+        let options = co_create::StringifyOptions::from_dictionary(&options);
+        match co_file::save(&path.to_string(), &variant, &options) {
+            Ok(()) => Error::OK,
+            Err(err) => err
+        }
+    }
+
+
+    #[func]
+    /// Checks that the value follows the schema, as described for the schema import option in the README.
+    /// Returns an empty string if it does, or the path of the first value that does not and what was expected.
+    fn check_schema(&mut self, value: Variant, schema: Variant) -> GString {
+        match co_import::validate(&value, &schema, "") {
+            Ok(()) => GString::new(),
+            Err(err) => GString::from(err)
+        }
     }
 
 
@@ -64,4 +175,1116 @@ impl CerealObject {
     fn stringify_raw(&mut self, variant: Variant) -> GString {
         co_create::stringify_raw(&variant)
     }
-}
\ No newline at end of file
+
+
+    #[func]
+    /// Stringifies the variant so that equal values always give the exact same text, e.g. to hash it.
+    fn stringify_canonical(&mut self, variant: Variant) -> GString {
+        co_create::stringify_canonical(&variant)
+    }
+
+
+    #[func]
+    /// Stringifies the variant with the given options, see the README for the list of options.
+    fn stringify_ex(&mut self, variant: Variant, options: Dictionary) -> GString {
+        co_create::stringify_with_options(&variant, &co_create::StringifyOptions::from_dictionary(&options))
+    }
+
+
+    #[func]
+    /// Encodes the variant in binary CE. The string table stores repeated strings only once.
+    fn stringify_binary(&mut self, variant: Variant, #[opt(default = false)] use_string_table: bool) -> PackedByteArray {
+        co_binary::encode(&variant, use_string_table)
+    }
+
+
+    #[func]
+    /// Decodes binary CE and returns the data. Returns null if decoding failed. Objects are only created if allowed, see parse_string.
+    fn parse_binary(&mut self, bytes: PackedByteArray, #[opt(default = false)] allow_objects: bool) -> Variant {
+        match co_binary::decode(&bytes.to_vec(), allow_objects) {
+            Ok(variant) => variant,
+            Err(e) => {
+                godot_error!("[CerealObject] {}", e.message);
+                Variant::nil()
+            }
+        }
+    }
+
+
+    #[func]
+    /// Decodes binary CE and returns a CerealObjectParseResult holding either the data or the error.
+    fn parse_binary_ex(&mut self, bytes: PackedByteArray, #[opt(default = false)] allow_objects: bool) -> Gd<CerealObjectParseResult> {
+        CerealObjectParseResult::from_result(co_binary::decode(&bytes.to_vec(), allow_objects))
+    }
+}
+
+
+#[derive(GodotClass)]
+#[class(tool, init, base=Resource)]
+/// Resource holding the data of a .ce file, loaded with load() or preload()
+struct CerealResource {
+    #[export]
+    data: Variant,
+
+    #[base]
+    base: Base<Resource>
+}
+
+
+#[derive(GodotClass)]
+#[class(tool, init, base=ResourceFormatLoader)]
+/// Loads .ce files as CerealResources
+struct CerealResourceLoader {
+    #[base]
+    base: Base<ResourceFormatLoader>
+}
+
+
+#[godot_api]
+impl IResourceFormatLoader for CerealResourceLoader {
+    fn get_recognized_extensions(&self) -> PackedStringArray {
+        PackedStringArray::from(&[GString::from("ce")])
+    }
+
+
+    fn handles_type(&self, type_: StringName) -> bool {
+        type_ == StringName::from("CerealResource") || type_ == StringName::from("Resource")
+    }
+
+
+    fn get_resource_type(&self, path: GString) -> GString {
+        if path.to_string().to_lowercase().ends_with(".ce") {
+            GString::from("CerealResource")
+        } else {
+            GString::new()
+        }
+    }
+
+
+    fn load(&self, path: GString, _original_path: GString, _use_sub_threads: bool, _cache_mode: i32) -> Variant {
+        // Only project files are trusted, like the scenes and resources that hold objects.
+        // Files elsewhere (user://, downloads...) may come from anyone.
+        let path = path.to_string();
+        match co_file::load(&path, path.starts_with("res://")) {
+            Ok(data) => {
+                let mut resource = CerealResource::new_gd();
+                resource.bind_mut().data = data;
+                resource.to_variant()
+            },
+            Err(co_file::LoadError::File(err)) => err.to_variant(),
+            Err(co_file::LoadError::Parse(err)) => {
+                godot_error!("[CerealObject] {}: {}", path, err);
+                Error::ERR_PARSE_ERROR.to_variant()
+            }
+        }
+    }
+}
+
+
+#[derive(GodotClass)]
+#[class(tool, init, base=ResourceFormatSaver)]
+/// Saves CerealResources as .ce files
+struct CerealResourceSaver {
+    #[base]
+    base: Base<ResourceFormatSaver>
+}
+
+
+#[godot_api]
+impl IResourceFormatSaver for CerealResourceSaver {
+    fn save(&mut self, resource: Gd<Resource>, path: GString, _flags: u32) -> Error {
+        let data = resource.get(StringName::from("data"));
+        match co_file::save(&path.to_string(), &data, &co_create::StringifyOptions::default()) {
+            Ok(()) => Error::OK,
+            Err(err) => err
+        }
+    }
+
+
+    fn recognize(&self, resource: Gd<Resource>) -> bool {
+        resource.is_class(GString::from("CerealResource"))
+    }
+
+
+    fn get_recognized_extensions(&self, resource: Gd<Resource>) -> PackedStringArray {
+        if self.recognize(resource) {
+            PackedStringArray::from(&[GString::from("ce")])
+        } else {
+            PackedStringArray::new()
+        }
+    }
+}
+
+
+#[derive(GodotClass)]
+#[class(tool, init, base=EditorImportPlugin)]
+/// Imports .ce files as binary resources, so that they are parsed and checked in the editor rather than at runtime
+struct CerealImportPlugin {
+    #[base]
+    base: Base<EditorImportPlugin>
+}
+
+
+impl CerealImportPlugin {
+    const IMPORTER_NAME: &'static str = "cereal_object.resource";
+
+    /// PROPERTY_HINT_FILE, for the schema option
+    const PROPERTY_HINT_FILE: i64 = 13;
+
+
+    fn _option(name: &str, default_value: Variant, property_hint: i64, hint_string: &str) -> Dictionary {
+        let mut option = Dictionary::new();
+        option.insert("name", name);
+        option.insert("default_value", default_value);
+        option.insert("property_hint", property_hint);
+        option.insert("hint_string", hint_string);
+        option
+    }
+}
+
+
+#[godot_api]
+impl IEditorImportPlugin for CerealImportPlugin {
+    fn get_importer_name(&self) -> GString {
+        GString::from(Self::IMPORTER_NAME)
+    }
+
+
+    fn get_visible_name(&self) -> GString {
+        GString::from("Cereal Object Resource")
+    }
+
+
+    fn get_recognized_extensions(&self) -> PackedStringArray {
+        PackedStringArray::from(&[GString::from("ce")])
+    }
+
+
+    fn get_save_extension(&self) -> GString {
+        GString::from("res")
+    }
+
+
+    fn get_resource_type(&self) -> GString {
+        GString::from("Resource")
+    }
+
+
+    fn get_preset_count(&self) -> i32 {
+        1
+    }
+
+
+    fn get_preset_name(&self, _preset_index: i32) -> GString {
+        GString::from("Default")
+    }
+
+
+    fn get_import_options(&self, _path: GString, _preset_index: i32) -> Array<Dictionary> {
+        let mut options = Array::new();
+        // Class of the imported resource, CerealResource keeps the data as is in its data property
+        options.push(Self::_option("target_class", Variant::from("CerealResource"), 0, ""));
+        // CE file describing the expected structure of the data, see the README
+        options.push(Self::_option("schema", Variant::from(""), Self::PROPERTY_HINT_FILE, "*.ce"));
+        options.push(Self::_option("compress", Variant::from(true), 0, ""));
+        options
+    }
+
+
+    fn get_option_visibility(&self, _path: GString, _option_name: StringName, _options: Dictionary) -> bool {
+        true
+    }
+
+
+    fn get_priority(&self) -> f32 {
+        1.0
+    }
+
+
+    fn get_import_order(&self) -> i32 {
+        0
+    }
+
+
+    fn import(&self, source_file: GString, save_path: GString, options: Dictionary, _platform_variants: Array<GString>, _gen_files: Array<GString>) -> Error {
+        let source_file = source_file.to_string();
+        let option = |name: &str| options.get(name).map_or(String::new(), |value| value.to_string());
+
+        // Errors are logged with the file, so that they show up in the editor output
+        let load = |path: &str| match co_file::load(path, true) {
+            Ok(data) => Ok(data),
+            Err(co_file::LoadError::File(err)) => {
+                godot_error!("[CerealObject] Cannot read {}: {:?}", path, err);
+                Err(err)
+            },
+            Err(co_file::LoadError::Parse(err)) => {
+                godot_error!("[CerealObject] {}: {}", path, err);
+                Err(Error::ERR_PARSE_ERROR)
+            }
+        };
+
+        let data = match load(&source_file) {
+            Ok(data) => data,
+            Err(err) => return err
+        };
+
+        let schema_path = option("schema");
+        if !schema_path.is_empty() {
+            let schema = match load(&schema_path) {
+                Ok(schema) => schema,
+                Err(err) => return err
+            };
+            if let Err(err) = co_import::validate(&data, &schema, "") {
+                godot_error!("[CerealObject] {} does not follow the schema {}: {}", source_file, schema_path, err);
+                return Error::ERR_INVALID_DATA;
+            }
+        }
+
+        let target_class = option("target_class");
+        let resource = if target_class.is_empty() || target_class == "CerealResource" {
+            let mut resource = CerealResource::new_gd();
+            resource.bind_mut().data = data;
+            resource.upcast()
+        } else {
+            match co_import::instantiate_resource(&target_class, &data) {
+                Ok(resource) => resource,
+                Err(err) => {
+                    godot_error!("[CerealObject] Cannot import {}: {}", source_file, err);
+                    return Error::ERR_INVALID_PARAMETER;
+                }
+            }
+        };
+
+        let compress = options.get("compress").map_or(true, |compress| compress.booleanize());
+        ResourceSaver::singleton()
+            .save_ex(resource)
+            .path(GString::from(format!("{}.{}", save_path, self.get_save_extension())))
+            .flags(if compress { SaverFlags::FLAG_COMPRESS } else { SaverFlags::FLAG_NONE })
+            .done()
+    }
+}
+
+
+#[derive(GodotClass)]
+#[class(tool, init, base=EditorExportPlugin)]
+/// Exports the source of imported .ce files along with their imported resource,
+/// so that load_file and CerealDocument can still read them at runtime
+struct CerealExportPlugin {
+    #[base]
+    base: Base<EditorExportPlugin>
+}
+
+
+#[godot_api]
+impl IEditorExportPlugin for CerealExportPlugin {
+    fn get_name(&self) -> GString {
+        GString::from("CerealObject")
+    }
+
+
+    fn export_file(&mut self, path: GString, _type_: GString, _features: PackedStringArray) {
+        if !path.to_string().ends_with(".ce") {
+            return;
+        }
+
+        // Files kept as is (or imported by something else) are exported by Godot as usual
+        let mut import = ConfigFile::new_gd();
+        if import.load(GString::from(format!("{}.import", path))) != Error::OK
+            || import.get_value(GString::from("remap"), GString::from("importer")).to_string() != CerealImportPlugin::IMPORTER_NAME {
+            return;
+        }
+
+        let source = FileAccess::get_file_as_bytes(path.clone());
+        self.base.add_file(path, source, false);
+    }
+}
+
+
+#[derive(GodotClass)]
+#[class(tool, init, base=EditorSyntaxHighlighter)]
+/// Highlights .ce files in the script editor
+struct CerealSyntaxHighlighter {
+    /// Color of each style, taken from the editor settings
+    colors: HashMap<Style, Color>,
+    /// Regions of each line of the text, None once the text has changed
+    lines: RefCell<Option<Vec<Vec<Region>>>>,
+
+    #[base]
+    base: Base<EditorSyntaxHighlighter>
+}
+
+
+impl CerealSyntaxHighlighter {
+    const STYLE_SETTINGS: [(Style, &'static str); 10] = [
+        (Style::Text, "text_editor/theme/highlighting/text_color"),
+        (Style::Symbol, "text_editor/theme/highlighting/symbol_color"),
+        (Style::Key, "text_editor/theme/highlighting/member_variable_color"),
+        (Style::String, "text_editor/theme/highlighting/string_color"),
+        (Style::Number, "text_editor/theme/highlighting/number_color"),
+        (Style::Keyword, "text_editor/theme/highlighting/keyword_color"),
+        (Style::Type, "text_editor/theme/highlighting/base_type_color"),
+        (Style::ClassName, "text_editor/theme/highlighting/engine_type_color"),
+        (Style::Anchor, "text_editor/theme/highlighting/function_color"),
+        (Style::Comment, "text_editor/theme/highlighting/comment_color"),
+    ];
+}
+
+
+#[godot_api]
+impl IEditorSyntaxHighlighter for CerealSyntaxHighlighter {
+    fn get_name(&self) -> GString {
+        GString::from("Cereal Object")
+    }
+
+
+    fn get_supported_languages(&self) -> PackedStringArray {
+        PackedStringArray::from(&[GString::from("ce")])
+    }
+
+
+    fn update_cache(&mut self) {
+        self.lines.replace(None);
+
+        // Godot only highlights again the lines that were edited, but strings and comments can span several lines
+        if let Some(mut text_edit) = self.base.get_text_edit() {
+            let on_lines_edited = Callable::from_object_method(self.base.clone(), "_on_lines_edited");
+            if !text_edit.is_connected(StringName::from("lines_edited_from"), on_lines_edited.clone()) {
+                text_edit.connect(StringName::from("lines_edited_from"), on_lines_edited);
+            }
+        }
+
+        let Some(settings) = EditorInterface::singleton().get_editor_settings() else {
+            return;
+        };
+        self.colors = Self::STYLE_SETTINGS.iter()
+            .map(|(style, setting)| (*style, settings.get_setting(GString::from(*setting)).try_to::<Color>().unwrap_or(Color::WHITE)))
+            .collect();
+    }
+
+
+    fn clear_highlighting_cache(&mut self) {
+        self.lines.replace(None);
+    }
+
+
+    fn get_line_syntax_highlighting(&self, line: i32) -> Dictionary {
+        let mut highlighting = Dictionary::new();
+        let Some(text_edit) = self.base.get_text_edit() else {
+            return highlighting;
+        };
+
+        // The whole text is highlighted at once after each change, then its lines are read from the cache
+        let mut lines = self.lines.borrow_mut();
+        let lines = lines.get_or_insert_with(|| co_highlight::highlight(&text_edit.get_text().to_string()));
+        for region in lines.get(line as usize).into_iter().flatten() {
+            let mut color = Dictionary::new();
+            color.insert("color", self.colors.get(&region.style).copied().unwrap_or(Color::WHITE));
+            highlighting.insert(region.column as i64, color);
+        }
+        highlighting
+    }
+}
+
+
+#[godot_api]
+impl CerealSyntaxHighlighter {
+    #[func]
+    fn _on_lines_edited(&self, _from_line: i64, _to_line: i64) {
+        self.lines.replace(None);
+    }
+}
+
+
+#[derive(GodotClass)]
+#[class(init, base=RefCounted)]
+/// Result of CerealObject.parse_string_ex, holds either the parsed data or the reason the parse failed
+struct CerealObjectParseResult {
+    data: Variant,
+    error_code: i32,
+    error_message: GString,
+    error_line: i64,
+    error_column: i64,
+    file_error: Option<Error>,
+
+    #[base]
+    base: Base<RefCounted>
+}
+
+
+impl CerealObjectParseResult {
+    fn from_result(result: Result<Variant, co_parser::ParseError>) -> Gd<Self> {
+        let mut parse_result = Self::new_gd();
+        {
+            let mut parse_result = parse_result.bind_mut();
+            match result {
+                Ok(data) => parse_result.data = data,
+                Err(err) => {
+                    parse_result.error_code = err.code as i32;
+                    parse_result.error_message = GString::from(err.message);
+                    parse_result.error_line = err.line as i64;
+                    parse_result.error_column = err.column as i64;
+                }
+            }
+        }
+        parse_result
+    }
+
+
+    fn from_load_result(result: Result<Variant, co_file::LoadError>, path: &str) -> Gd<Self> {
+        match result {
+            Ok(data) => Self::from_result(Ok(data)),
+            Err(co_file::LoadError::Parse(err)) => Self::from_result(Err(err)),
+            Err(co_file::LoadError::File(err)) => {
+                let mut parse_result = Self::new_gd();
+                {
+                    let mut parse_result = parse_result.bind_mut();
+                    parse_result.error_code = ParseErrorCode::FileError as i32;
+                    parse_result.error_message = GString::from(format!("Cannot read {}: {:?}", path, err));
+                    parse_result.file_error = Some(err);
+                }
+                parse_result
+            }
+        }
+    }
+}
+
+
+#[godot_api]
+impl CerealObjectParseResult {
+    #[constant] const ERROR_OK: i32 = ParseErrorCode::Ok as i32;
+    #[constant] const ERROR_UNEXPECTED_EOF: i32 = ParseErrorCode::UnexpectedEof as i32;
+    #[constant] const ERROR_UNEXPECTED_CHARACTER: i32 = ParseErrorCode::UnexpectedCharacter as i32;
+    #[constant] const ERROR_UNEXPECTED_TOKEN: i32 = ParseErrorCode::UnexpectedToken as i32;
+    #[constant] const ERROR_UNKNOWN_IDENTIFIER: i32 = ParseErrorCode::UnknownIdentifier as i32;
+    #[constant] const ERROR_UNKNOWN_CLASS: i32 = ParseErrorCode::UnknownClass as i32;
+    #[constant] const ERROR_UNKNOWN_REFERENCE: i32 = ParseErrorCode::UnknownReference as i32;
+    #[constant] const ERROR_UNTERMINATED_STRING: i32 = ParseErrorCode::UnterminatedString as i32;
+    #[constant] const ERROR_UNTERMINATED_COMMENT: i32 = ParseErrorCode::UnterminatedComment as i32;
+    #[constant] const ERROR_MALFORMED_STRING: i32 = ParseErrorCode::MalformedString as i32;
+    #[constant] const ERROR_MALFORMED_NUMBER: i32 = ParseErrorCode::MalformedNumber as i32;
+    #[constant] const ERROR_TYPE_MISMATCH: i32 = ParseErrorCode::TypeMismatch as i32;
+    #[constant] const ERROR_UNSUPPORTED_TYPE: i32 = ParseErrorCode::UnsupportedType as i32;
+    #[constant] const ERROR_MAX_DEPTH: i32 = ParseErrorCode::MaxDepth as i32;
+    #[constant] const ERROR_INVALID_HEADER: i32 = ParseErrorCode::InvalidHeader as i32;
+    #[constant] const ERROR_FILE_ERROR: i32 = ParseErrorCode::FileError as i32;
+    #[constant] const ERROR_OBJECT_NOT_ALLOWED: i32 = ParseErrorCode::ObjectNotAllowed as i32;
+
+
+    #[func]
+    /// Returns the parsed data, null if the parse failed
+    fn get_data(&self) -> Variant {
+        self.data.clone()
+    }
+
+
+    #[func]
+    /// Returns OK if the parse succeeded, the error of the file if it could not be read, ERR_PARSE_ERROR otherwise
+    fn get_error(&self) -> Error {
+        if let Some(err) = self.file_error {
+            return err;
+        }
+        if self.error_code == ParseErrorCode::Ok as i32 {
+            Error::OK
+        } else {
+            Error::ERR_PARSE_ERROR
+        }
+    }
+
+
+    #[func]
+    /// Returns one of the ERROR_* constants describing what went wrong
+    fn get_error_code(&self) -> i32 {
+        self.error_code
+    }
+
+
+    #[func]
+    fn get_error_message(&self) -> GString {
+        self.error_message.clone()
+    }
+
+
+    #[func]
+    /// Returns the line of the error, starting at 1
+    fn get_error_line(&self) -> i64 {
+        self.error_line
+    }
+
+
+    #[func]
+    /// Returns the column of the error in characters, starting at 1
+    fn get_error_column(&self) -> i64 {
+        self.error_column
+    }
+}
+
+
+
+/// Work done by a CerealTask on a worker thread
+enum AsyncJob {
+    /// Parse the string
+    Parse(String),
+    /// Read and parse the file at the path
+    Load(String),
+}
+
+
+#[derive(GodotClass)]
+#[class(base=RefCounted)]
+/// Parse running on the WorkerThreadPool, see CerealObject.parse_string_async and load_file_async.
+///
+/// Everything it holds is behind locks, as the worker thread uses it while the main thread may check on it.
+struct CerealTask {
+    /// Taken by the worker thread when it starts
+    job: Mutex<Option<AsyncJob>>,
+    /// Path of the file being loaded, for error messages
+    path: String,
+    allow_objects: bool,
+    /// Result of the job, set by the worker thread when it is done
+    outcome: Mutex<Option<Result<Variant, co_file::LoadError>>>,
+    /// Id in the WorkerThreadPool, -1 once the task has been waited for
+    task_id: AtomicI64,
+    /// Held while waiting for the worker thread, so that it is only waited for once
+    waiting: Mutex<()>,
+    /// Set once the task is waited for
+    result: Mutex<Option<Gd<CerealObjectParseResult>>>,
+    /// Keeps the task alive while it runs, even if nothing else references it
+    running: Mutex<Option<Gd<CerealTask>>>,
+
+    #[base]
+    base: Base<RefCounted>
+}
+
+
+impl CerealTask {
+    fn start(job: AsyncJob, allow_objects: bool) -> Gd<Self> {
+        let path = match &job {
+            AsyncJob::Load(path) => path.clone(),
+            AsyncJob::Parse(_) => String::new()
+        };
+        let task = Gd::from_init_fn(|base| CerealTask {
+            job: Mutex::new(Some(job)),
+            path,
+            allow_objects,
+            outcome: Mutex::new(None),
+            task_id: AtomicI64::new(-1),
+            waiting: Mutex::new(()),
+            result: Mutex::new(None),
+            running: Mutex::new(None),
+            base
+        });
+
+        *task.bind().running.lock().unwrap() = Some(task.clone());
+        let task_id = WorkerThreadPool::singleton().add_task(Callable::from_object_method(task.clone(), "_run"));
+        task.bind().task_id.store(task_id, Ordering::Release);
+        task
+    }
+
+
+    /// Waits for the worker thread and builds the result the first time it is called
+    fn _complete(&self) -> Gd<CerealObjectParseResult> {
+        if let Some(result) = &*self.result.lock().unwrap() {
+            return result.clone();
+        }
+
+        // The result is not locked while waiting, so that is_done does not block meanwhile
+        {
+            let _waiting = self.waiting.lock().unwrap();
+            let task_id = self.task_id.swap(-1, Ordering::AcqRel);
+            if task_id >= 0 {
+                // Waiting for the task lets the pool release it, even though it is already done when called from _finish
+                WorkerThreadPool::singleton().wait_for_task_completion(task_id);
+            }
+        }
+
+        let mut result = self.result.lock().unwrap();
+        if let Some(result) = &*result {
+            return result.clone();
+        }
+
+        let outcome = self.outcome.lock().unwrap().take()
+            .unwrap_or(Err(co_file::LoadError::File(Error::ERR_BUG)));
+        result.insert(CerealObjectParseResult::from_load_result(outcome, &self.path)).clone()
+    }
+}
+
+
+#[godot_api]
+impl CerealTask {
+    #[signal]
+    /// Emitted on the main thread with the CerealObjectParseResult once the parse is done
+    fn completed(result: Gd<CerealObjectParseResult>);
+
+
+    #[func]
+    /// Returns true once the parse is done. The result is then available without blocking.
+    fn is_done(&self) -> bool {
+        self.result.lock().unwrap().is_some() || self.outcome.lock().unwrap().is_some()
+    }
+
+
+    #[func]
+    /// Blocks until the parse is done, and returns its CerealObjectParseResult.
+    /// Must not be called on the main thread when objects are allowed: creating them may need the
+    /// main thread (e.g. to load their script), which would then wait forever.
+    fn wait(&self) -> Gd<CerealObjectParseResult> {
+        self._complete()
+    }
+
+
+    #[func]
+    /// Runs the job, called on a worker thread
+    fn _run(&self) {
+        let job = self.job.lock().unwrap().take();
+        let outcome = match job {
+            Some(AsyncJob::Parse(text)) => co_parser::parse_ex(&text, self.allow_objects).map_err(co_file::LoadError::Parse),
+            Some(AsyncJob::Load(path)) => co_file::load(&path, self.allow_objects),
+            None => return
+        };
+        *self.outcome.lock().unwrap() = Some(outcome);
+
+        self.base.clone().call_deferred(StringName::from("_finish"), &[]);
+    }
+
+
+    #[func]
+    /// Completes the task on the main thread once the worker thread is done. completed is only
+    /// emitted from here, even if wait already built the result on another thread.
+    fn _finish(&self) {
+        let result = self._complete();
+        self.base.clone().emit_signal(StringName::from("completed"), &[result.to_variant()]);
+        self.running.lock().unwrap().take();
+    }
+}
+
+
+#[derive(GodotClass)]
+#[class(init, base=RefCounted)]
+/// Editable CE document that keeps comments, key order, whitespaces and number spellings
+/// of everything that is not modified.
+struct CerealDocument {
+    document: Option<co_cst::Document>,
+    error_message: GString,
+    error_line: i64,
+    error_column: i64,
+
+    #[base]
+    base: Base<RefCounted>
+}
+
+
+#[godot_api]
+impl CerealDocument {
+    #[func]
+    /// Parses the given CE string. Returns ERR_PARSE_ERROR on failure, see get_error_message, get_error_line and get_error_column.
+    /// Objects are only created if allowed, see CerealObject.parse_string.
+    fn parse(&mut self, text: GString, #[opt(default = false)] allow_objects: bool) -> Error {
+        match co_cst::Document::parse(text.to_string(), allow_objects) {
+            Ok(document) => {
+                self.document = Some(document);
+                self.error_message = GString::new();
+                self.error_line = 0;
+                self.error_column = 0;
+                Error::OK
+            },
+            Err(err) => {
+                self.document = None;
+                self.error_message = GString::from(err.message);
+                self.error_line = err.line as i64;
+                self.error_column = err.column as i64;
+                Error::ERR_PARSE_ERROR
+            }
+        }
+    }
+
+
+    #[func]
+    /// Returns the value at the path, made of dictionary keys and array indices separated by dots (e.g. "enemies.goblin.hp"). Returns null if there is none.
+    fn get_value(&self, path: GString) -> Variant {
+        self.document.as_ref()
+            .and_then(|document| document.get(&path.to_string()))
+            .unwrap_or_default()
+    }
+
+
+    #[func]
+    fn has_value(&self, path: GString) -> bool {
+        self.document.as_ref().is_some_and(|document| document.has(&path.to_string()))
+    }
+
+
+    #[func]
+    /// Replaces the value at the path, or adds it if its parent is a dictionary. Everything else in the document is left untouched.
+    fn set_value(&mut self, path: GString, value: Variant) -> Error {
+        match self.document.as_mut() {
+            Some(document) if document.set(&path.to_string(), &value) => Error::OK,
+            Some(_) => Error::ERR_DOES_NOT_EXIST,
+            None => Error::ERR_UNCONFIGURED
+        }
+    }
+
+
+    #[func]
+    /// Returns the CE string of the document, with all the modifications
+    fn get_text(&self) -> GString {
+        match &self.document {
+            Some(document) => GString::from(document.source()),
+            None => GString::new()
+        }
+    }
+
+
+    #[func]
+    fn get_error_message(&self) -> GString {
+        self.error_message.clone()
+    }
+
+
+    #[func]
+    fn get_error_line(&self) -> i64 {
+        self.error_line
+    }
+
+
+    #[func]
+    fn get_error_column(&self) -> i64 {
+        self.error_column
+    }
+}
+
+
+
+#[derive(GodotClass)]
+#[class(init, base=RefCounted)]
+/// Pull parser reading a CE string fed in chunks, one event at a time, without building the whole value
+struct CerealReader {
+    reader: co_reader::Reader,
+    value: Variant,
+    anchor: GString,
+    array_type: GString,
+    error_code: i32,
+    error_message: GString,
+    error_line: i64,
+    error_column: i64,
+
+    #[base]
+    base: Base<RefCounted>
+}
+
+
+#[godot_api]
+impl CerealReader {
+    #[constant] const EVENT_NEED_MORE_DATA: i32 = 0;
+    #[constant] const EVENT_BEGIN_DICTIONARY: i32 = 1;
+    #[constant] const EVENT_END_DICTIONARY: i32 = 2;
+    #[constant] const EVENT_BEGIN_ARRAY: i32 = 3;
+    #[constant] const EVENT_END_ARRAY: i32 = 4;
+    #[constant] const EVENT_BEGIN_OBJECT: i32 = 5;
+    #[constant] const EVENT_END_OBJECT: i32 = 6;
+    #[constant] const EVENT_KEY: i32 = 7;
+    #[constant] const EVENT_VALUE: i32 = 8;
+    #[constant] const EVENT_REFERENCE: i32 = 9;
+    #[constant] const EVENT_END: i32 = 10;
+    #[constant] const EVENT_ERROR: i32 = 11;
+
+
+    #[func]
+    /// Adds the bytes after the input already fed
+    fn feed(&mut self, bytes: PackedByteArray) {
+        self.reader.feed(&bytes.to_vec());
+    }
+
+
+    #[func]
+    /// Adds the text after the input already fed
+    fn feed_string(&mut self, text: GString) {
+        self.reader.feed(text.to_string().as_bytes());
+    }
+
+
+    #[func]
+    /// Allows objects in the input, see CerealObject.parse_string. They are not allowed by default.
+    fn set_allow_objects(&mut self, allow_objects: bool) {
+        self.reader.set_allow_objects(allow_objects);
+    }
+
+
+    #[func]
+    /// Tells the reader that the whole input has been fed, so that it stops waiting for more
+    fn finish(&mut self) {
+        self.reader.finish();
+    }
+
+
+    #[func]
+    /// Reads the next event and returns one of the EVENT_* constants.
+    /// Returns EVENT_NEED_MORE_DATA when the input fed so far ends in the middle of the event, call feed or finish and try again.
+    fn next(&mut self) -> i32 {
+        self.value = Variant::nil();
+        self.anchor = GString::new();
+        self.array_type = GString::new();
+
+        let event = match self.reader.next() {
+            Ok(event) => event,
+            Err(err) => {
+                self.error_code = err.code as i32;
+                self.error_message = GString::from(err.message);
+                self.error_line = err.line as i64;
+                self.error_column = err.column as i64;
+                return Self::EVENT_ERROR;
+            }
+        };
+
+        let (anchor, event) = match event {
+            co_reader::Event::NeedMoreData => return Self::EVENT_NEED_MORE_DATA,
+            co_reader::Event::EndDictionary => return Self::EVENT_END_DICTIONARY,
+            co_reader::Event::EndArray => return Self::EVENT_END_ARRAY,
+            co_reader::Event::EndObject => return Self::EVENT_END_OBJECT,
+            co_reader::Event::End => return Self::EVENT_END,
+            co_reader::Event::Key(key) => {
+                self.value = key;
+                return Self::EVENT_KEY;
+            },
+            co_reader::Event::Reference(name) => {
+                self.value = Variant::from(name);
+                return Self::EVENT_REFERENCE;
+            },
+            co_reader::Event::BeginDictionary(anchor) => (anchor, Self::EVENT_BEGIN_DICTIONARY),
+            co_reader::Event::BeginArray(anchor, array_type) => {
+                self.array_type = GString::from(array_type.unwrap_or_default());
+                (anchor, Self::EVENT_BEGIN_ARRAY)
+            },
+            co_reader::Event::BeginObject(anchor, class_name) => {
+                self.value = Variant::from(class_name);
+                (anchor, Self::EVENT_BEGIN_OBJECT)
+            },
+            co_reader::Event::Value(anchor, value) => {
+                self.value = value;
+                (anchor, Self::EVENT_VALUE)
+            }
+        };
+
+        self.anchor = GString::from(anchor.unwrap_or_default());
+        event
+    }
+
+
+    #[func]
+    /// Returns the value of EVENT_VALUE, the key of EVENT_KEY, the class name of EVENT_BEGIN_OBJECT
+    /// or the anchor name of EVENT_REFERENCE. Returns null for the other events.
+    fn get_value(&self) -> Variant {
+        self.value.clone()
+    }
+
+
+    #[func]
+    /// Returns the anchor name of the value or container that was just read, an empty string if it has none
+    fn get_anchor(&self) -> GString {
+        self.anchor.clone()
+    }
+
+
+    #[func]
+    /// Returns the type of the array of EVENT_BEGIN_ARRAY (e.g. "i32"), an empty string for untyped arrays
+    fn get_array_type(&self) -> GString {
+        self.array_type.clone()
+    }
+
+
+    #[func]
+    /// Returns the number of containers the reader is in
+    fn get_depth(&self) -> i64 {
+        self.reader.depth() as i64
+    }
+
+
+    #[func]
+    /// Returns the number of bytes read so far
+    fn get_offset(&self) -> i64 {
+        self.reader.offset() as i64
+    }
+
+
+    #[func]
+    /// Returns one of the CerealObjectParseResult.ERROR_* constants after EVENT_ERROR
+    fn get_error_code(&self) -> i32 {
+        self.error_code
+    }
+
+
+    #[func]
+    fn get_error_message(&self) -> GString {
+        self.error_message.clone()
+    }
+
+
+    #[func]
+    fn get_error_line(&self) -> i64 {
+        self.error_line
+    }
+
+
+    #[func]
+    fn get_error_column(&self) -> i64 {
+        self.error_column
+    }
+}
+
+
+
+#[derive(GodotClass)]
+#[class(init, base=RefCounted)]
+/// Writes a CE string piece by piece to a file, a stream peer or a string, without building the whole value
+struct CerealWriter {
+    writer: co_writer::Writer,
+    file: Option<Gd<FileAccess>>,
+    stream: Option<Gd<StreamPeer>>,
+    /// Text written when there is neither a file nor a stream
+    text: String,
+
+    #[base]
+    base: Base<RefCounted>
+}
+
+
+impl CerealWriter {
+    /// Text is sent to the file or the stream once it is at least this long
+    const FLUSH_SIZE: usize = 65536;
+
+
+    fn _written(&mut self, result: Result<(), String>) -> Error {
+        if let Err(err) = result {
+            godot_error!("[CerealObject] {}", err);
+            return Error::ERR_INVALID_PARAMETER;
+        }
+
+        if self.writer.is_done() || self.writer.output_len() >= Self::FLUSH_SIZE {
+            return self.flush();
+        }
+        Error::OK
+    }
+}
+
+
+#[godot_api]
+impl CerealWriter {
+    #[func]
+    /// Sets the options used to write the text, see CerealObject.stringify_ex
+    fn set_options(&mut self, options: Dictionary) {
+        self.writer.set_options(co_create::StringifyOptions::from_dictionary(&options));
+    }
+
+
+    #[func]
+    /// Writes the text to the file from now on
+    fn set_file(&mut self, file: Gd<FileAccess>) {
+        self.file = Some(file);
+        self.stream = None;
+    }
+
+
+    #[func]
+    /// Writes the text to the stream peer from now on
+    fn set_stream(&mut self, stream: Gd<StreamPeer>) {
+        self.file = None;
+        self.stream = Some(stream);
+    }
+
+
+    #[func]
+    fn begin_dictionary(&mut self) -> Error {
+        let result = self.writer.begin_dictionary();
+        self._written(result)
+    }
+
+
+    #[func]
+    fn end_dictionary(&mut self) -> Error {
+        let result = self.writer.end_dictionary();
+        self._written(result)
+    }
+
+
+    #[func]
+    /// Starts an object of the given class, its properties are then written with key and value
+    fn begin_object(&mut self, class_name: GString) -> Error {
+        let result = self.writer.begin_object(&class_name.to_string());
+        self._written(result)
+    }
+
+
+    #[func]
+    fn end_object(&mut self) -> Error {
+        let result = self.writer.end_object();
+        self._written(result)
+    }
+
+
+    #[func]
+    fn begin_array(&mut self) -> Error {
+        let result = self.writer.begin_array();
+        self._written(result)
+    }
+
+
+    #[func]
+    /// Starts a typed array (e.g. "i32" or "Vector2"), whose elements are then written with value
+    fn begin_typed_array(&mut self, array_type: GString) -> Error {
+        let result = self.writer.begin_typed_array(&array_type.to_string());
+        self._written(result)
+    }
+
+
+    #[func]
+    fn end_array(&mut self) -> Error {
+        let result = self.writer.end_array();
+        self._written(result)
+    }
+
+
+    #[func]
+    /// Writes a dictionary key or a property name, to be followed by its value
+    fn key(&mut self, key: Variant) -> Error {
+        let result = self.writer.key(&key);
+        self._written(result)
+    }
+
+
+    #[func]
+    /// Writes a whole value, which may be an element of the current array or the value of the last key
+    fn value(&mut self, value: Variant) -> Error {
+        let result = self.writer.value(&value);
+        self._written(result)
+    }
+
+
+    #[func]
+    /// Sends the text written so far to the file or the stream. This is done automatically once the whole value is written.
+    fn flush(&mut self) -> Error {
+        let text = self.writer.take_output();
+        if let Some(file) = &mut self.file {
+            file.store_buffer(PackedByteArray::from(text.as_bytes()));
+            file.get_error()
+        } else if let Some(stream) = &mut self.stream {
+            stream.put_data(PackedByteArray::from(text.as_bytes()))
+        } else {
+            self.text += &text;
+            Error::OK
+        }
+    }
+
+
+    #[func]
+    /// Returns the text written so far, when there is neither a file nor a stream
+    fn get_text(&mut self) -> GString {
+        self.flush();
+        GString::from(self.text.as_str())
+    }
+
+
+    #[func]
+    /// Returns true once the whole value has been written
+    fn is_done(&self) -> bool {
+        self.writer.is_done()
+    }
+}
@@ -0,0 +1,408 @@
+extends SceneTree
+## Tests of what needs a running engine, the rest is tested with cargo test.
+## Run from a project where the plugin is installed:
+## godot --headless -s res://addons/cerealobj/tests/test_cereal_object.gd
+
+
+var failures := 0
+var current_test := ""
+
+
+func _initialize() -> void:
+	for method in get_method_list():
+		if method.name.begins_with("test_"):
+			current_test = method.name
+			# Tests that need the main loop (e.g. for deferred calls) await its frames
+			await call(method.name)
+
+	print("%d failure(s)" % failures)
+	quit(1 if failures else 0)
+
+
+func check(condition: bool, message: String) -> void:
+	if not condition:
+		failures += 1
+		printerr("%s: %s" % [current_test, message])
+
+
+func check_eq(value: Variant, expected: Variant) -> void:
+	check(typeof(value) == typeof(expected) and value == expected, "expected %s, got %s" % [var_to_str(expected), var_to_str(value)])
+
+
+func test_document_references() -> void:
+	var document := CerealDocument.new()
+	check_eq(document.parse("{ a: &id { hp: 5 }, b: *id, c: &n 3, d: *n }"), OK)
+
+	check(document.has_value("b"), "b exists")
+	check_eq(document.get_value("b"), { "hp": 5 })
+	check(is_same(document.get_value("a"), document.get_value("b")), "a and b are the same dictionary")
+	check_eq(document.get_value("d"), 3)
+
+	# Paths go into anchored containers
+	check(document.has_value("a.hp"), "a.hp exists")
+	check_eq(document.get_value("a.hp"), 5)
+
+	# and through references to them
+	check(document.has_value("b.hp"), "b.hp exists")
+	check_eq(document.get_value("b.hp"), 5)
+	check_eq(document.set_value("b.hp", 6), OK)
+	check_eq(document.get_text(), "{ a: &id { hp: 6 }, b: *id, c: &n 3, d: *n }")
+	check_eq(document.set_value("b.mp", 2), OK)
+	check_eq(document.get_value("a.mp"), 2)
+	check(not document.has_value("d.x"), "d references a number")
+
+	# A reference points to the last anchor of that name declared before it
+	check_eq(document.parse("[&x { v: 1 }, *x, &x { v: 2 }, *x]"), OK)
+	check_eq(document.get_value("1.v"), 1)
+	check_eq(document.get_value("3.v"), 2)
+
+
+func test_document_set_keeps_anchor() -> void:
+	var document := CerealDocument.new()
+	document.parse("{ a: &n 3, b: *n }")
+	check_eq(document.set_value("a", 4), OK)
+	check_eq(document.get_text(), "{ a: &n 4, b: *n }")
+	check_eq(document.get_value("b"), 4)
+
+
+func test_objects_are_opt_in() -> void:
+	var result: CerealObjectParseResult = CerealObject.parse_string_ex("Resource { resource_name: \"a\" }")
+	check_eq(result.get_error_code(), CerealObjectParseResult.ERROR_OBJECT_NOT_ALLOWED)
+
+	var resource = CerealObject.parse_string("Resource { resource_name: \"a\" }", true)
+	check(resource is Resource, "objects are parsed once allowed")
+
+	var bytes := CerealObject.stringify_binary(Resource.new())
+	check_eq(CerealObject.parse_binary_ex(bytes).get_error_code(), CerealObjectParseResult.ERROR_OBJECT_NOT_ALLOWED)
+	check(CerealObject.parse_binary(bytes, true) is Resource, "binary objects are parsed once allowed")
+
+
+func test_failed_object_is_freed() -> void:
+	var orphans := Performance.get_monitor(Performance.OBJECT_ORPHAN_NODE_COUNT)
+	var result: CerealObjectParseResult = CerealObject.parse_string_ex("Node { name: }", true)
+	check(result.get_error_code() != CerealObjectParseResult.ERROR_OK, "the properties do not parse")
+	check_eq(Performance.get_monitor(Performance.OBJECT_ORPHAN_NODE_COUNT), orphans)
+
+
+func test_sibling_objects_are_freed() -> void:
+	var orphans := Performance.get_monitor(Performance.OBJECT_ORPHAN_NODE_COUNT)
+	var result: CerealObjectParseResult = CerealObject.parse_string_ex("{ a: Node {}, b: }", true)
+	check_eq(result.get_error_code(), CerealObjectParseResult.ERROR_UNEXPECTED_TOKEN)
+	result = CerealObject.parse_string_ex("[Node {}, Node {}, Node { name: }]", true)
+	check(result.get_error_code() != CerealObjectParseResult.ERROR_OK, "the last object does not parse")
+	check(CerealDocument.new().parse("[Node {}, Node { name: }]", true) != OK, "the document does not parse")
+	check_eq(Performance.get_monitor(Performance.OBJECT_ORPHAN_NODE_COUNT), orphans)
+
+	var nodes := [Node.new(), Node.new()]
+	var bytes := CerealObject.stringify_binary(nodes, false)
+	for node in nodes:
+		node.free()
+	bytes.resize(bytes.size() - 1)
+	check(CerealObject.parse_binary_ex(bytes, true).get_error_code() != CerealObjectParseResult.ERROR_OK, "the data is truncated")
+	check_eq(Performance.get_monitor(Performance.OBJECT_ORPHAN_NODE_COUNT), orphans)
+
+
+func test_script_without_class_name() -> void:
+	var path := "user://test_script.gd"
+	var file := FileAccess.open(path, FileAccess.WRITE)
+	file.store_string("extends RefCounted\n\nvar hp := 0\n")
+	file.close()
+
+	var object := RefCounted.new()
+	object.set_script(load(path))
+	object.hp = 5
+	for parsed in [
+		CerealObject.parse_string(CerealObject.stringify(object), true),
+		CerealObject.parse_binary(CerealObject.stringify_binary(object, false), true),
+	]:
+		check(parsed.get_script() != null and parsed.get_script().resource_path == path, "the script is set from its path")
+		check_eq(parsed.hp, 5)
+	DirAccess.remove_absolute(path)
+
+	var result: CerealObjectParseResult = CerealObject.parse_string_ex('RefCounted { script: "user://missing.gd" }', true)
+	check_eq(result.get_error_code(), CerealObjectParseResult.ERROR_UNKNOWN_CLASS)
+
+
+func test_cyclic_containers() -> void:
+	var array := [1]
+	array.append(array)
+	var dictionary := { "a": array }
+	dictionary["self"] = dictionary
+
+	var parsed = CerealObject.parse_string(CerealObject.stringify_raw([dictionary, dictionary]))
+	check(is_same(parsed[0], parsed[1]), "the shared dictionary is written once")
+	check(is_same(parsed[0]["self"], parsed[0]), "the dictionary references itself")
+	check(is_same(parsed[0]["a"][1], parsed[0]["a"]), "the array references itself")
+
+
+func test_typed_arrays_share_their_content() -> void:
+	var shared := { "hp": 5 }
+	var dictionaries: Array[Dictionary] = [shared, shared]
+	var arrays: Array[Array] = []
+	arrays.append(arrays)
+	var resources: Array[Resource] = [Resource.new()]
+	resources.append(resources[0])
+
+	for parsed in [
+		CerealObject.parse_string(CerealObject.stringify_raw([dictionaries, arrays, resources]), true),
+		CerealObject.parse_string(CerealObject.stringify([dictionaries, arrays, resources]), true),
+		CerealObject.parse_binary(CerealObject.stringify_binary([dictionaries, arrays, resources], false), true),
+	]:
+		check_eq(parsed[0], [{ "hp": 5 }, { "hp": 5 }])
+		check(is_same(parsed[0][0], parsed[0][1]), "the dictionary in the typed array is written once")
+		check(is_same(parsed[1][0], parsed[1]), "the typed array references itself")
+		check(is_same(parsed[2][0], parsed[2][1]), "the resource in the typed array is written once")
+
+
+func test_equal_containers_are_not_shared() -> void:
+	var parsed = CerealObject.parse_string(CerealObject.stringify_raw([[1], [1], { "a": 1 }, { "a": 1 }]))
+	check_eq(parsed, [[1], [1], { "a": 1 }, { "a": 1 }])
+	check(not is_same(parsed[0], parsed[1]), "equal arrays stay distinct")
+	check(not is_same(parsed[2], parsed[3]), "equal dictionaries stay distinct")
+
+
+func test_canonical_ignores_sharing() -> void:
+	var shared := { "hp": 5 }
+	check_eq(CerealObject.stringify_canonical([shared, shared]), CerealObject.stringify_canonical([{ "hp": 5 }, { "hp": 5 }]))
+
+	var cycle := []
+	cycle.append(cycle)
+	check_eq(CerealObject.stringify_canonical(cycle), "&id1 [*id1]")
+
+
+func test_canonical_numbers() -> void:
+	check_eq(CerealObject.stringify_canonical(-0.0), CerealObject.stringify_canonical(0.0))
+	check_eq(CerealObject.stringify_canonical(Vector2(1, -0.0)), "Vector2(1f,0f)")
+	check_eq(CerealObject.stringify_canonical(Rect2i(1, 2, 3, 4)), "Rect2i(1i,2i;3i,4i)")
+
+
+func test_writer_checks_typed_array_ranges() -> void:
+	var writer := CerealWriter.new()
+	writer.set_options({ "indent": "" })
+	check_eq(writer.begin_typed_array("B"), OK)
+	check_eq(writer.value(255), OK)
+	check_eq(writer.value(300), ERR_INVALID_PARAMETER)
+	check_eq(writer.value(-1), ERR_INVALID_PARAMETER)
+	check_eq(writer.end_array(), OK)
+	check_eq(CerealObject.parse_string(writer.get_text()), PackedByteArray([255]))
+
+
+func test_save_file_default_options() -> void:
+	check_eq(CerealObject.save_file("user://test_save_file.ce", { "a": 1 }), OK)
+	check_eq(CerealObject.load_file("user://test_save_file.ce"), { "a": 1 })
+	DirAccess.remove_absolute("user://test_save_file.ce")
+
+
+func test_task_wait() -> void:
+	var task: CerealTask = CerealObject.parse_string_async("[1, 2]")
+	var result: CerealObjectParseResult = task.wait()
+	check(task.is_done(), "the task is done once waited for")
+	check_eq(result.get_data(), [1, 2])
+	check(is_same(task.wait(), result), "the result is only built once")
+
+
+func test_task_completed_on_main_thread() -> void:
+	var task: CerealTask = CerealObject.parse_string_async("[1]")
+	var emitted_on := []
+	task.completed.connect(func(_result): emitted_on.append(OS.get_thread_caller_id()))
+
+	var thread := Thread.new()
+	thread.start(task.wait)
+	var result: CerealObjectParseResult = thread.wait_to_finish()
+	check_eq(result.get_data(), [1])
+	check_eq(emitted_on, [])
+
+	for i in 10:
+		if not emitted_on.is_empty():
+			break
+		await process_frame
+	check_eq(emitted_on, [OS.get_main_thread_id()])
+
+
+func test_nested_object_not_allowed() -> void:
+	var result: CerealObjectParseResult = CerealObject.parse_string_ex("[1, MyItem { damage: 5 }]")
+	check_eq(result.get_error_code(), CerealObjectParseResult.ERROR_OBJECT_NOT_ALLOWED)
+	check_eq(result.get_error_column(), 5)
+
+
+func test_parse_errors() -> void:
+	var result: CerealObjectParseResult = CerealObject.parse_string_ex("{\n\ta: 1,\n\tb: }")
+	check_eq(result.get_error_code(), CerealObjectParseResult.ERROR_UNEXPECTED_TOKEN)
+	check_eq([result.get_error_line(), result.get_error_column()], [3, 5])
+	check_eq(result.get_data(), null)
+
+	result = CerealObject.parse_string_ex("[".repeat(5000))
+	check_eq(result.get_error_code(), CerealObjectParseResult.ERROR_MAX_DEPTH)
+
+
+func test_document_keeps_formatting() -> void:
+	var document := CerealDocument.new()
+	check_eq(document.parse("{\n\ta: 1, # hit points\n\tb: 2.50,\n}"), OK)
+	check_eq(document.set_value("a", 5), OK)
+	check_eq(document.get_text(), "{\n\ta: 5, # hit points\n\tb: 2.50,\n}")
+
+
+func test_non_string_keys() -> void:
+	var value := { 1: "int", 2.5: "float", Vector2i(1, 2): "vector", [1]: "array", "a b": "string" }
+	check_eq(CerealObject.parse_string(CerealObject.stringify(value)), value)
+
+
+func test_string_names_and_node_paths() -> void:
+	check_eq(CerealObject.parse_string("&\"idle\""), &"idle")
+	check_eq(CerealObject.parse_string("^\"../Player:position\""), ^"../Player:position")
+
+	var names: Array[StringName] = [&"a", &"b"]
+	var parsed = CerealObject.parse_string(CerealObject.stringify(names))
+	check_eq(parsed, names)
+	check(parsed.is_typed() and parsed.get_typed_builtin() == TYPE_STRING_NAME, "the array stays typed")
+
+
+func test_math_types() -> void:
+	for value in [Quaternion(0.1, 0.2, 0.3, 0.9), Basis(Vector3(1, 2, 3), Vector3(4, 5, 6), Vector3(7, 8, 9)),
+			Plane(Vector3(0, 1, 0), 2.5), Projection(Vector4(1, 2, 3, 4), Vector4(5, 6, 7, 8), Vector4(9, 10, 11, 12), Vector4(13, 14, 15, 16))]:
+		check_eq(CerealObject.parse_string(CerealObject.stringify(value)), value)
+
+
+func test_packed_arrays() -> void:
+	for value in [PackedByteArray([0, 255]), PackedInt32Array([-1, 2147483647]), PackedInt64Array([-9223372036854775808]),
+			PackedFloat32Array([0.1, -2.5]), PackedFloat64Array([0.1, 1e300]), PackedStringArray(["a", "b\n"]),
+			PackedVector2Array([Vector2(1, 2)]), PackedVector3Array([Vector3(1, 2, 3)]), PackedColorArray([Color(0.1, 0.2, 0.3)]),
+			PackedByteArray(), PackedStringArray()]:
+		var parsed = CerealObject.parse_string(CerealObject.stringify(value))
+		check_eq(parsed, value)
+		check_eq(CerealObject.parse_binary(CerealObject.stringify_binary(value, false)), value)
+
+
+func test_special_floats() -> void:
+	check(is_nan(CerealObject.parse_string("nan")), "nan parses")
+	check_eq(CerealObject.parse_string("[inf, -inf]"), [INF, -INF])
+	check_eq(1.0 / CerealObject.parse_string(CerealObject.stringify(-0.0)), -INF)
+
+
+func test_stringify_options() -> void:
+	var options := { "indent": "", "sort_keys": true, "quote_style": "single", "type_suffixes": false, "float_precision": 1 }
+	check_eq(CerealObject.stringify_ex({ "b": 1.26, "a": "x" }, options), "{a:'x',b:1.3}")
+	check_eq(CerealObject.stringify_ex(PackedByteArray([255]), { "indent": "", "hex_bytes": true }), "[B,0xFF]")
+	check_eq(CerealObject.stringify_ex([1], { "indent": "\t", "max_inline_width": 0, "trailing_commas": true }), "[\n\t1,\n]")
+
+
+func test_hex_integers() -> void:
+	var value := { "layer": 5, "mask": -16, "hp": 10, "name": "a" }
+	var options := { "indent": "", "hex_keys": ["layer", "mask", "name"] }
+	check_eq(CerealObject.stringify_ex(value, options), '{layer:0x5,mask:-0x10,hp:10,name:"a"}')
+	check_eq(CerealObject.parse_string(CerealObject.stringify_ex(value, options)), value)
+	check_eq(CerealObject.stringify_ex([255, 1.5], { "indent": "", "hex_ints": true }), "[0xFF,1.5f]")
+	check_eq(CerealObject.stringify_ex({ "layer": 5 }, { "hex_keys": PackedStringArray(["layer"]) }), "{layer: 0x5}")
+
+	var writer := CerealWriter.new()
+	writer.set_options(options)
+	writer.begin_dictionary()
+	writer.key("layer")
+	writer.value(5)
+	writer.key("hp")
+	writer.value(10)
+	writer.end_dictionary()
+	check_eq(writer.get_text(), "{layer:0x5,hp:10}")
+
+
+func test_reader_in_chunks() -> void:
+	var text := "{ a: [1, 25f], b: \"xy\" }"
+	var reader := CerealReader.new()
+	var events := []
+	var fed := 0
+	while true:
+		var event := reader.next()
+		if event == CerealReader.EVENT_NEED_MORE_DATA:
+			# One character at a time, so that every event is cut somewhere
+			if fed < text.length():
+				reader.feed_string(text[fed])
+				fed += 1
+			else:
+				reader.finish()
+			continue
+		events.append([event, reader.get_value()])
+		if event == CerealReader.EVENT_END or event == CerealReader.EVENT_ERROR:
+			break
+
+	check_eq(events, [
+		[CerealReader.EVENT_BEGIN_DICTIONARY, null], [CerealReader.EVENT_KEY, "a"], [CerealReader.EVENT_BEGIN_ARRAY, null],
+		[CerealReader.EVENT_VALUE, 1], [CerealReader.EVENT_VALUE, 25.0], [CerealReader.EVENT_END_ARRAY, null],
+		[CerealReader.EVENT_KEY, "b"], [CerealReader.EVENT_VALUE, "xy"], [CerealReader.EVENT_END_DICTIONARY, null],
+		[CerealReader.EVENT_END, null],
+	])
+
+
+func test_reader_rejects_data_after_the_value() -> void:
+	var reader := CerealReader.new()
+	reader.feed_string("{ a: 1 } garbage")
+	reader.finish()
+	var events := []
+	for i in 4:
+		events.append(reader.next())
+	check_eq(events, [CerealReader.EVENT_BEGIN_DICTIONARY, CerealReader.EVENT_KEY, CerealReader.EVENT_VALUE, CerealReader.EVENT_END_DICTIONARY])
+	check_eq(reader.next(), CerealReader.EVENT_ERROR)
+	check_eq([reader.get_error_line(), reader.get_error_column()], [1, 10])
+
+
+func test_writer() -> void:
+	var writer := CerealWriter.new()
+	check_eq(writer.begin_dictionary(), OK)
+	check_eq(writer.key("a"), OK)
+	check_eq(writer.begin_array(), OK)
+	check_eq(writer.value(1), OK)
+	check_eq(writer.value(Vector2(1, 2)), OK)
+	check_eq(writer.end_dictionary(), ERR_INVALID_PARAMETER)
+	check_eq(writer.begin_object("a b"), ERR_INVALID_PARAMETER)
+	check_eq(writer.end_array(), OK)
+	check_eq(writer.value(2), ERR_INVALID_PARAMETER)
+	check_eq(writer.key("b"), OK)
+	check_eq(writer.value("x"), OK)
+	check(not writer.is_done(), "the dictionary is still open")
+	check_eq(writer.end_dictionary(), OK)
+	check(writer.is_done(), "the whole value is written")
+	check_eq(CerealObject.parse_string(writer.get_text()), { "a": [1, Vector2(1, 2)], "b": "x" })
+
+
+func test_resource_loader_and_saver() -> void:
+	var path := "user://test_resource.ce"
+	check_eq(CerealObject.save_file(path, { "hp": 5 }), OK)
+
+	var resource = ResourceLoader.load(path, "", ResourceLoader.CACHE_MODE_IGNORE)
+	check(resource is CerealResource, "the file loads as a CerealResource")
+	check_eq(resource.data, { "hp": 5 })
+
+	resource.data = { "hp": 6 }
+	check_eq(ResourceSaver.save(resource, path), OK)
+	check_eq(CerealObject.load_file(path), { "hp": 6 })
+	DirAccess.remove_absolute(path)
+
+
+func test_resource_loader_rejects_objects_outside_the_project() -> void:
+	var path := "user://test_object.ce"
+	var file := FileAccess.open(path, FileAccess.WRITE)
+	file.store_string("{ item: Resource { resource_name: \"sword\" } }")
+	file.close()
+
+	check_eq(ResourceLoader.load(path, "", ResourceLoader.CACHE_MODE_IGNORE), null)
+	check_eq(CerealObject.load_file_ex(path).get_error_code(), CerealObjectParseResult.ERROR_OBJECT_NOT_ALLOWED)
+	DirAccess.remove_absolute(path)
+
+
+func test_check_schema() -> void:
+	var schema = CerealObject.parse_string('{ name: "String", hp: "int", drops: [{ item: "String", chance: "float" }], extra: "Variant" }')
+	var value := { "name": "goblin", "hp": 5, "drops": [{ "item": "gold", "chance": 0.5 }], "extra": [1, "a"] }
+	check_eq(CerealObject.check_schema(value, schema), "")
+
+	value.drops.append({ "item": "gem", "chance": 1 })
+	check_eq(CerealObject.check_schema(value, schema), "drops.1.chance: expected float, found int")
+	value.drops.pop_back()
+	value.erase("hp")
+	check_eq(CerealObject.check_schema(value, schema), "hp: missing")
+	check_eq(CerealObject.check_schema([], schema), "root: expected Dictionary, found Array")
+
+	# Objects match their class and its base classes, and dictionaries check their properties
+	var resource := Resource.new()
+	resource.resource_name = "sword"
+	check_eq(CerealObject.check_schema(resource, "RefCounted"), "")
+	check_eq(CerealObject.check_schema(resource, { "resource_name": "String" }), "")
+	check_eq(CerealObject.check_schema(resource, "Node"), "root: expected Node, found Resource")